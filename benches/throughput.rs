@@ -0,0 +1,83 @@
+use blockchain::{Blockchain, BlockchainBuilder, Clock, Transaction};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+const SEED: u64 = 7;
+const CHAIN_LENGTH: u32 = 200;
+const DIFFICULTY: usize = 1;
+
+/// A chain with a funded sender and a recipient, identical on every run.
+fn bench_chain() -> (Blockchain, String, String) {
+    let mut blockchain = BlockchainBuilder::new()
+        .difficulty(DIFFICULTY)
+        .clock(Clock::manual(0, 10))
+        .genesis_timestamp(0)
+        .seed(SEED)
+        .build()
+        .expect("benchmark config is valid");
+    let sender = blockchain.create_wallet();
+    let recipient = blockchain.create_wallet();
+    blockchain.fund_genesis(&sender, 1_000_000.0).expect("genesis is open");
+    (blockchain, sender, recipient)
+}
+
+/// `count` signed transfers of one token, numbered from the sender's next
+/// nonce so they can be submitted in order.
+fn signed_transfers(blockchain: &Blockchain, sender: &str, recipient: &str, count: u64) -> Vec<Transaction> {
+    let first_nonce = blockchain.next_nonce(sender);
+    (0..count)
+        .map(|offset| {
+            let mut transaction = blockchain.new_transaction(sender, recipient, 1.0).nonce(first_nonce + offset).build();
+            assert!(blockchain.sign_with_wallet(&mut transaction), "sender was created by the chain");
+            transaction
+        })
+        .collect()
+}
+
+fn validation(c: &mut Criterion) {
+    let (mut blockchain, sender, recipient) = bench_chain();
+    for _ in 0..CHAIN_LENGTH {
+        for transaction in signed_transfers(&blockchain, &sender, &recipient, 1) {
+            blockchain.add_transaction(transaction).expect("sender is funded");
+        }
+        blockchain.mine_pending_transactions(&recipient).expect("empty blocks are allowed");
+    }
+    c.bench_function("is_chain_valid/200 blocks", |b| b.iter(|| black_box(blockchain.is_chain_valid())));
+}
+
+fn insertion(c: &mut Criterion) {
+    let (blockchain, sender, recipient) = bench_chain();
+    let transfers = signed_transfers(&blockchain, &sender, &recipient, 100);
+    c.bench_function("add_transaction/100 transfers", |b| {
+        b.iter_batched(
+            || (blockchain.clone(), transfers.clone()),
+            |(mut blockchain, transfers)| {
+                for transaction in transfers {
+                    blockchain.add_transaction(transaction).expect("sender is funded");
+                }
+                blockchain
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn mining(c: &mut Criterion) {
+    let (blockchain, sender, recipient) = bench_chain();
+    let mut loaded = blockchain.clone();
+    for transaction in signed_transfers(&blockchain, &sender, &recipient, 10) {
+        loaded.add_transaction(transaction).expect("sender is funded");
+    }
+    c.bench_function("mine_pending_transactions/10 transfers", |b| {
+        b.iter_batched(
+            || loaded.clone(),
+            |mut blockchain| {
+                blockchain.mine_pending_transactions(&recipient).expect("empty blocks are allowed");
+                blockchain
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, validation, insertion, mining);
+criterion_main!(benches);
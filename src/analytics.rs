@@ -0,0 +1,326 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::block::Block;
+use crate::blockchain::{Blockchain, ReorgStats};
+use crate::transaction::{Transaction, TransactionKind};
+
+/// On-chain activity of one address, as returned by
+/// [`Blockchain::address_stats`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AddressStats {
+    pub sent_count: usize,
+    pub received_count: usize,
+    pub total_sent: f64,
+    pub total_received: f64,
+    /// Height of the last block with a transaction touching the address;
+    /// `None` if the address never appears on chain.
+    pub last_active_height: Option<u32>,
+}
+
+/// Supply figures for dashboards, as returned by
+/// [`Blockchain::supply_info`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SupplyInfo {
+    /// Everything minted by coinbase transactions on chain.
+    pub total_issued: f64,
+    /// Liquid balances. Staked coins and coins still unbonding after an
+    /// unstake are not included.
+    pub circulating: f64,
+    pub staked: f64,
+    /// Issued coins that are neither liquid, staked nor unbonding. Nothing
+    /// on chain destroys coins, so anything but zero means the balances
+    /// have drifted from the chain.
+    pub burned: f64,
+    pub current_reward: f64,
+    pub next_halving_height: u32,
+}
+
+/// Chain-wide figures for the statistics screen and monitoring, as returned
+/// by [`Blockchain::stats`]. Serializes to the JSON stats output.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChainStats {
+    pub height: u32,
+    /// Accounts this node tracks balances for.
+    pub wallets: usize,
+    pub unique_addresses: usize,
+    /// Leading zero bits the next block's hash needs.
+    pub difficulty_bits: usize,
+    pub pending_transactions: usize,
+    pub mempool_pressure: f64,
+    pub supply: SupplyInfo,
+    pub reorgs: ReorgStats,
+}
+
+/// New coins a block creates: what its coinbase transactions pay out, less
+/// the fees they pass on from the block's other transactions.
+fn minted_in(block: &Block) -> f64 {
+    let paid: f64 = block.transactions.iter().filter(|tx| tx.is_coinbase()).map(|tx| tx.amount).sum();
+    let fees: f64 = block.transactions.iter().map(|tx| tx.fee).sum();
+    paid - fees
+}
+
+impl Blockchain {
+    /// Total coins minted by coinbase transactions in blocks up to and
+    /// including `height`, read from the chain itself so genesis
+    /// allocations and any non-standard issuance are reflected.
+    pub fn cumulative_supply_at_height(&self, height: u32) -> f64 {
+        self.blocks().iter().take(height as usize + 1).map(minted_in).sum()
+    }
+
+    /// `(height, cumulative supply)` for every block, for plotting the
+    /// emission schedule.
+    pub fn emission_curve(&self) -> Vec<(u32, f64)> {
+        let mut supply = 0.0;
+        self.blocks()
+            .iter()
+            .map(|block| {
+                supply += minted_in(block);
+                (block.index, supply)
+            })
+            .collect()
+    }
+
+    pub fn supply_info(&self) -> SupplyInfo {
+        let total_issued = self.cumulative_supply_at_height(self.height());
+        let (circulating, staked) = (self.total_liquid(), self.total_staked());
+        SupplyInfo {
+            total_issued,
+            circulating,
+            staked,
+            burned: total_issued - circulating - staked - self.total_unbonding(),
+            current_reward: self.current_mining_reward(),
+            next_halving_height: self.next_halving_height(),
+        }
+    }
+
+    /// Pending transactions counted by fee rate (fee per byte) into
+    /// `buckets` equal ranges spanning the lowest to the highest rate in the
+    /// mempool, as `(lower bound, count)` pairs in ascending order. The
+    /// highest rate falls in the last bucket. An empty mempool or zero
+    /// buckets gives no buckets, and a mempool whose transactions all pay the
+    /// same rate gives one.
+    pub fn mempool_fee_histogram(&self, buckets: usize) -> Vec<(f64, usize)> {
+        let rates: Vec<f64> = self.pending_transactions().iter().map(Transaction::fee_rate).collect();
+        if buckets == 0 || rates.is_empty() {
+            return Vec::new();
+        }
+        let min = rates.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = rates.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        if min == max {
+            return vec![(min, rates.len())];
+        }
+        let width = (max - min) / buckets as f64;
+        let mut counts = vec![0; buckets];
+        for rate in rates {
+            counts[(((rate - min) / width) as usize).min(buckets - 1)] += 1;
+        }
+        counts.into_iter().enumerate().map(|(bucket, count)| (min + width * bucket as f64, count)).collect()
+    }
+
+    /// Audits the value flow of every block after genesis: its coinbase
+    /// transactions together must pay exactly the block's subsidy plus the
+    /// fees of its other transactions, so no block creates or destroys
+    /// coins. Returns the first block that does not, with how much more
+    /// (positive) or less (negative) its coinbase paid. Transfers need no
+    /// audit of their own: under the account model each debits its sender
+    /// exactly its amount plus its fee.
+    pub fn audit_fees(&self) -> Option<(u32, f64)> {
+        self.first_fee_discrepancy(self.blocks())
+    }
+
+    fn first_fee_discrepancy(&self, blocks: &[Block]) -> Option<(u32, f64)> {
+        blocks.iter().skip(1).find_map(|block| {
+            let fees: f64 = block.transactions.iter().filter(|tx| !tx.is_coinbase()).map(|tx| tx.fee).sum();
+            // Summed in the order the coinbase transactions pay them out.
+            let (treasury_share, miner_share) = self.split_subsidy(self.reward_at_height(block.index));
+            let mut expected = miner_share + fees;
+            if self.config().treasury.is_some() {
+                expected += treasury_share;
+            }
+            let paid: f64 = block.transactions.iter().filter(|tx| tx.is_coinbase()).map(|tx| tx.amount).sum();
+            (paid != expected).then_some((block.index, paid - expected))
+        })
+    }
+
+    pub fn stats(&self) -> ChainStats {
+        ChainStats {
+            height: self.height(),
+            wallets: self.wallet_count(),
+            unique_addresses: self.unique_addresses_in_chain(),
+            difficulty_bits: self.next_target_bits(),
+            pending_transactions: self.pending_transactions().len(),
+            mempool_pressure: self.mempool_pressure(),
+            supply: self.supply_info(),
+            reorgs: self.reorg_stats(),
+        }
+    }
+
+    /// Sum of `Block::volume` over the whole chain.
+    pub fn total_volume(&self) -> f64 {
+        self.blocks().iter().map(Block::volume).sum()
+    }
+
+    /// Distinct addresses that have sent or received coins on chain,
+    /// excluding the coinbase pseudo-address. Unlike `wallet_count` this
+    /// includes addresses that were never created locally.
+    pub fn unique_addresses_in_chain(&self) -> usize {
+        self.blocks()
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .flat_map(|tx| [tx.from.as_str(), tx.to.as_str()])
+            .filter(|address| *address != "0")
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// Sent and received counts and totals for `address`, in one pass over
+    /// the chain. Coinbase payouts count as received; stakes and unstakes
+    /// only mark the address as active, since no coins change hands.
+    pub fn address_stats(&self, address: &str) -> AddressStats {
+        let mut stats = AddressStats::default();
+        for block in self.blocks() {
+            for tx in &block.transactions {
+                if tx.from != address && tx.to != address {
+                    continue;
+                }
+                stats.last_active_height = Some(block.index);
+                if tx.kind != TransactionKind::Transfer {
+                    continue;
+                }
+                if tx.from == address {
+                    stats.sent_count += 1;
+                    stats.total_sent += tx.amount;
+                }
+                if tx.to == address {
+                    stats.received_count += 1;
+                    stats.total_received += tx.amount;
+                }
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::BlockchainBuilder;
+    use crate::clock::Clock;
+
+    #[test]
+    fn volume_counts_transfers_but_not_issuance_or_stakes() {
+        let mut blockchain = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).build().unwrap();
+        let (alice, bob, miner) = (blockchain.create_wallet(), blockchain.create_wallet(), blockchain.create_wallet());
+        blockchain.fund_genesis(&alice, 100.0).unwrap();
+        let mut transactions = vec![
+            blockchain.new_transaction(&alice, &bob, 10.0).build(),
+            blockchain.new_transaction(&alice, &bob, 15.0).nonce(1).build(),
+            blockchain.new_transaction(&alice, &alice, 5.0).nonce(2).kind(TransactionKind::Stake).build(),
+        ];
+        for transaction in &mut transactions {
+            blockchain.sign_with_wallet(transaction);
+        }
+        for transaction in transactions {
+            blockchain.add_transaction(transaction).unwrap();
+        }
+        blockchain.mine_pending_transactions(&miner).unwrap();
+        blockchain.mine_until_height(2, &miner);
+
+        assert_eq!(blockchain.blocks()[1].volume(), 25.0);
+        assert_eq!(blockchain.blocks()[2].volume(), 0.0);
+        assert_eq!(blockchain.total_volume(), 25.0);
+    }
+
+    #[test]
+    fn fee_histogram_buckets_pending_transactions_by_fee_rate() {
+        let mut blockchain = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).build().unwrap();
+        let (alice, bob) = (blockchain.create_wallet(), blockchain.create_wallet());
+        assert!(blockchain.mempool_fee_histogram(4).is_empty());
+        blockchain.fund_genesis(&alice, 100.0).unwrap();
+        for (nonce, fee) in [0.0, 0.0, 1.0, 4.0].into_iter().enumerate() {
+            let mut transaction = blockchain.new_transaction(&alice, &bob, 1.0).nonce(nonce as u64).fee(fee).build();
+            blockchain.sign_with_wallet(&mut transaction);
+            blockchain.add_transaction(transaction).unwrap();
+        }
+        let top_rate = blockchain.pending_transactions()[3].fee_rate();
+
+        assert_eq!(blockchain.mempool_fee_histogram(2), vec![(0.0, 3), (top_rate / 2.0, 1)]);
+        assert_eq!(blockchain.mempool_fee_histogram(1), vec![(0.0, 4)]);
+        assert!(blockchain.mempool_fee_histogram(0).is_empty());
+    }
+
+    #[test]
+    fn fee_audit_finds_a_forged_coinbase() {
+        let mut blockchain = BlockchainBuilder::new()
+            .difficulty(1)
+            .clock(Clock::manual(0, 1))
+            .seed(1)
+            .treasury("0xtreasury", 0.1)
+            .build()
+            .unwrap();
+        let (alice, bob, miner) = (blockchain.create_wallet(), blockchain.create_wallet(), blockchain.create_wallet());
+        blockchain.fund_genesis(&alice, 100.0).unwrap();
+        for fee in [0.25, 0.5] {
+            let mut transaction = blockchain.new_transaction(&alice, &bob, 10.0).fee(fee).build();
+            blockchain.sign_with_wallet(&mut transaction);
+            blockchain.add_transaction(transaction).unwrap();
+            blockchain.mine_pending_transactions(&miner).unwrap();
+        }
+        blockchain.mine_until_height(3, &miner);
+        assert_eq!(blockchain.audit_fees(), None);
+
+        let mut forged = blockchain.blocks().to_vec();
+        forged[2].transactions[0].amount += 5.0;
+        assert_eq!(blockchain.first_fee_discrepancy(&forged), Some((2, 5.0)));
+        forged[1].transactions[0].amount -= 0.25;
+        assert_eq!(blockchain.first_fee_discrepancy(&forged), Some((1, -0.25)));
+    }
+
+    #[test]
+    fn supply_accounts_for_every_issued_coin() {
+        let mut blockchain = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).build().unwrap();
+        let (alice, bob, miner) = (blockchain.create_wallet(), blockchain.create_wallet(), blockchain.create_wallet());
+        blockchain.fund_genesis(&alice, 100.0).unwrap();
+        let transactions = [
+            blockchain.new_transaction(&alice, &bob, 10.0).fee(0.5).build(),
+            blockchain.new_transaction(&alice, &alice, 40.0).nonce(1).kind(TransactionKind::Stake).build(),
+            blockchain.new_transaction(&alice, &alice, 15.0).nonce(2).kind(TransactionKind::Unstake).build(),
+        ];
+        // The unstake needs the stake mined first.
+        for mut transaction in transactions {
+            blockchain.sign_with_wallet(&mut transaction);
+            blockchain.add_transaction(transaction).unwrap();
+            blockchain.mine_pending_transactions(&miner).unwrap();
+        }
+
+        let supply = blockchain.supply_info();
+        assert_eq!(supply.total_issued, 400.0);
+        assert_eq!((supply.staked, blockchain.total_unbonding()), (25.0, 15.0));
+        assert_eq!(supply.circulating, 360.0);
+        assert_eq!(supply.burned, 0.0);
+    }
+
+    #[test]
+    fn stats_json_reports_reorgs() {
+        let mut blockchain = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).build().unwrap();
+        let miner = blockchain.create_wallet();
+        blockchain.mine_until_height(3, &miner);
+        // Branches, each by a new miner, that replace the last `depth`
+        // blocks with one more.
+        for depth in [1, 2] {
+            let rival = blockchain.create_wallet();
+            let mut branch = blockchain.clone();
+            for _ in 0..depth {
+                branch.undo_last_block();
+            }
+            branch.mine_until_height(blockchain.height() + 1, &rival);
+            blockchain.replace_chain(branch.blocks().to_vec()).unwrap();
+        }
+
+        let stats = serde_json::to_value(blockchain.stats()).unwrap();
+        assert_eq!(stats["reorgs"], serde_json::json!({ "count": 2, "max_depth": 2 }));
+        assert_eq!(stats["height"], 5);
+    }
+}
@@ -0,0 +1,362 @@
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::transaction::{Transaction, TransactionKind};
+
+/// Bytes a block occupies beyond its transactions: index (4), timestamp
+/// (8), merkle root, previous hash and hash (64 hex characters each), nonce
+/// (8), proof-of-work version (4) and target (4).
+pub const BLOCK_HEADER_BYTES: usize = 4 + 8 + 64 + 64 + 64 + 8 + 4 + 4;
+
+/// Merkle root of a block without transactions.
+const EMPTY_MERKLE_ROOT: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub index: u32,
+    pub timestamp: i64,
+    pub transactions: Vec<Transaction>,
+    /// `merkle_root` of `transactions`. The hash commits to the transactions
+    /// through it.
+    pub merkle_root: String,
+    pub previous_hash: String,
+    pub hash: String,
+    /// Proof-of-work counter, hashed with the rest of the header. 64 bits so
+    /// the search space cannot run out at any practical difficulty.
+    pub nonce: u64,
+    /// Proof-of-work rule set the block was mined under. Version 0 is the
+    /// original hash; later versions are mixed into the hash so blocks mined
+    /// under one version never validate under another.
+    #[serde(default)]
+    pub pow_version: u32,
+    /// Leading zero bits the hash was mined to, as checked by
+    /// `meets_difficulty_bits`. Not part of the hash: validation derives the
+    /// target each height must use and requires this to match it, so a
+    /// block cannot claim an easier one.
+    pub target_bits: usize,
+}
+
+impl Block {
+    pub fn new(index: u32, timestamp: i64, transactions: Vec<Transaction>, previous_hash: String, difficulty: usize) -> Block {
+        Block::with_pow_version(index, timestamp, transactions, previous_hash, difficulty, 0)
+    }
+
+    /// Like `new`, but mined under proof-of-work version `pow_version`.
+    pub fn with_pow_version(
+        index: u32,
+        timestamp: i64,
+        transactions: Vec<Transaction>,
+        previous_hash: String,
+        difficulty: usize,
+        pow_version: u32,
+    ) -> Block {
+        Block::with_target_bits(index, timestamp, transactions, previous_hash, difficulty * 4, pow_version)
+    }
+
+    /// Like `with_pow_version`, but the target is given in leading zero bits
+    /// as checked by `meets_difficulty_bits`.
+    pub fn with_target_bits(
+        index: u32,
+        timestamp: i64,
+        transactions: Vec<Transaction>,
+        previous_hash: String,
+        target_bits: usize,
+        pow_version: u32,
+    ) -> Block {
+        Block::with_starting_nonce(index, timestamp, transactions, previous_hash, target_bits, pow_version, 0)
+    }
+
+    /// Like `with_target_bits`, but the nonce search starts at `nonce`.
+    pub(crate) fn with_starting_nonce(
+        index: u32,
+        timestamp: i64,
+        transactions: Vec<Transaction>,
+        previous_hash: String,
+        target_bits: usize,
+        pow_version: u32,
+        nonce: u64,
+    ) -> Block {
+        let mut block = Block {
+            index,
+            timestamp,
+            merkle_root: merkle_root(&transactions),
+            transactions,
+            previous_hash,
+            hash: String::new(),
+            nonce,
+            pow_version,
+            target_bits,
+        };
+        block.mine();
+        block
+    }
+
+    /// Serialized size: the header plus each transaction's canonical size.
+    pub fn size(&self) -> usize {
+        BLOCK_HEADER_BYTES + self.transactions.iter().map(Transaction::serialized_size).sum::<usize>()
+    }
+
+    /// Coins moved by the block's transfers. Coinbase issuance and stake
+    /// operations are not counted.
+    pub fn volume(&self) -> f64 {
+        self.transactions
+            .iter()
+            .filter(|tx| !tx.is_coinbase() && tx.kind == TransactionKind::Transfer)
+            .map(|tx| tx.amount)
+            .sum()
+    }
+
+    pub fn calculate_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        let mut data = format!("{}{}{}{}{}", self.index, self.timestamp, &self.merkle_root, &self.previous_hash, self.nonce);
+        // Version 0 hashes the bare header.
+        if self.pow_version > 0 {
+            data.push_str(&format!("v{}", self.pow_version));
+        }
+        hasher.update(data.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Whether the stored merkle root matches the transactions, i.e. none
+    /// were changed, added or removed since the block was mined.
+    pub fn merkle_root_matches(&self) -> bool {
+        self.merkle_root == merkle_root(&self.transactions)
+    }
+
+    /// Searches nonces until the hash meets `target_bits`. A target of 0
+    /// takes no work: the first hash is kept.
+    fn mine(&mut self) {
+        self.hash = self.calculate_hash();
+        while !meets_difficulty_bits(&self.hash, self.target_bits) {
+            match self.nonce.checked_add(1) {
+                Some(nonce) => self.nonce = nonce,
+                // Out of nonces: a later timestamp opens a fresh search space.
+                None => {
+                    self.timestamp += 1;
+                    self.nonce = 0;
+                }
+            }
+            self.hash = self.calculate_hash();
+        }
+    }
+}
+
+/// Blocks are identified by their hash, which commits to every other field
+/// of an intact block. A block changed since it was mined therefore only
+/// equals another with the same changes: besides the stored hashes, the
+/// hash of the current header and the merkle root of the current
+/// transactions have to match too.
+impl PartialEq for Block {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash
+            && self.calculate_hash() == other.calculate_hash()
+            && merkle_root(&self.transactions) == merkle_root(&other.transactions)
+    }
+}
+
+impl Eq for Block {}
+
+impl Hash for Block {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash.hash(state);
+    }
+}
+
+/// Root of the merkle tree over the `witness_hash` of each transaction, in
+/// order. Each parent is the SHA-256 of its children's hex hashes joined
+/// together; a level with an odd count pairs its last hash with itself. A
+/// single transaction is its own root, and no transactions give a root of
+/// all zeros.
+pub fn merkle_root(transactions: &[Transaction]) -> String {
+    let mut level: Vec<String> = transactions.iter().map(Transaction::witness_hash).collect();
+    if level.is_empty() {
+        return EMPTY_MERKLE_ROOT.to_string();
+    }
+    while level.len() > 1 {
+        level = level.chunks(2).map(|pair| merkle_parent(&pair[0], pair.get(1).unwrap_or(&pair[0]))).collect();
+    }
+    level.remove(0)
+}
+
+fn merkle_parent(left: &str, right: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Evidence that a transaction is in a block without the rest of the
+/// block: its position and the sibling hash at each level of the merkle
+/// tree, from the leaves up. The position says which side each sibling is
+/// on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub siblings: Vec<String>,
+}
+
+/// Builds the proof for `transactions[index]` against `merkle_root` of
+/// `transactions`, or `None` if `index` is out of range.
+pub fn merkle_proof(transactions: &[Transaction], index: usize) -> Option<MerkleProof> {
+    if index >= transactions.len() {
+        return None;
+    }
+    let mut level: Vec<String> = transactions.iter().map(Transaction::witness_hash).collect();
+    let mut position = index;
+    let mut siblings = Vec::new();
+    while level.len() > 1 {
+        // The last hash of an odd level is its own sibling.
+        let sibling = (position ^ 1).min(level.len() - 1);
+        siblings.push(level[sibling].clone());
+        level = level.chunks(2).map(|pair| merkle_parent(&pair[0], pair.get(1).unwrap_or(&pair[0]))).collect();
+        position /= 2;
+    }
+    Some(MerkleProof { index, siblings })
+}
+
+/// Whether `proof` leads from the leaf `witness_hash` to `root`.
+pub fn verify_merkle_proof(witness_hash: &str, proof: &MerkleProof, root: &str) -> bool {
+    let mut position = proof.index;
+    let mut hash = witness_hash.to_string();
+    for sibling in &proof.siblings {
+        hash = if position.is_multiple_of(2) { merkle_parent(&hash, sibling) } else { merkle_parent(sibling, &hash) };
+        position /= 2;
+    }
+    position == 0 && hash == root
+}
+
+/// Whether `hash` satisfies the proof-of-work target for `difficulty`: at
+/// least `difficulty` leading `0` hex digits.
+pub fn meets_difficulty(hash: &str, difficulty: usize) -> bool {
+    meets_difficulty_bits(hash, difficulty * 4)
+}
+
+/// Whether the hex `hash` starts with at least `bits` zero bits: its first
+/// `bits / 4` digits are `0` and, when `bits % 4` is not zero, the digit
+/// after them is below `16 >> (bits % 4)`. For example 10 bits means two
+/// `0` digits followed by `0`–`3`. A multiple of four is exactly
+/// `meets_difficulty` with a quarter of the bits.
+pub fn meets_difficulty_bits(hash: &str, bits: usize) -> bool {
+    let (zeros, remainder) = (bits / 4, bits % 4);
+    let digits = hash.as_bytes();
+    if digits.len() < zeros || !digits[..zeros].iter().all(|&digit| digit == b'0') {
+        return false;
+    }
+    if remainder == 0 {
+        return true;
+    }
+    match digits.get(zeros).and_then(|&digit| (digit as char).to_digit(16)) {
+        Some(value) => value < 16 >> remainder,
+        None => false,
+    }
+}
+
+/// Checks a block's proof of work: the stored hash must match its contents
+/// and meet the difficulty target.
+pub fn verify_block_pow(block: &Block, difficulty: usize) -> bool {
+    block.merkle_root_matches() && block.hash == block.calculate_hash() && meets_difficulty(&block.hash, difficulty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_block() -> Block {
+        let transactions = vec![
+            Transaction::coinbase(String::from("0xminer"), 100.0, 1),
+            Transaction::new(String::from("0xalice"), String::from("0xbob"), 10.0),
+        ];
+        Block::new(1, 1_000, transactions, String::from("0abc"), 1)
+    }
+
+    #[test]
+    fn difficulty_counts_leading_zero_digits() {
+        assert!(meets_difficulty("000f", 3));
+        assert!(!meets_difficulty("000f", 4));
+        assert!(meets_difficulty("0000", 4));
+        assert!(meets_difficulty("abcd", 0));
+        assert!(!meets_difficulty("00", 3));
+    }
+
+    #[test]
+    fn blocks_with_the_same_contents_are_equal() {
+        assert!(sample_block() == sample_block());
+    }
+
+    #[test]
+    fn tampered_block_is_not_equal() {
+        let original = sample_block();
+        let mut tampered = original.clone();
+        tampered.transactions[1].amount = 1000.0;
+        assert!(tampered != original);
+
+        let mut restamped = original.clone();
+        restamped.timestamp += 1;
+        assert!(restamped != original);
+    }
+
+    #[test]
+    fn partial_digit_difficulty_bounds_the_next_digit() {
+        // 10 bits: two zero digits, then a digit below 4.
+        assert!(meets_difficulty_bits("003f", 10));
+        assert!(!meets_difficulty_bits("004f", 10));
+        assert!(meets_difficulty_bits("07ff", 5));
+        assert!(!meets_difficulty_bits("08ff", 5));
+        assert!(meets_difficulty_bits("001f", 11));
+        assert!(!meets_difficulty_bits("002f", 11));
+        assert!(!meets_difficulty_bits("00", 10));
+        assert!(meets_difficulty_bits("abcd", 0));
+    }
+
+    #[test]
+    fn mining_continues_past_the_last_nonce() {
+        let transactions = sample_block().transactions;
+        let block = Block::with_starting_nonce(1, 1_000, transactions, String::from("0abc"), 8, 0, u64::MAX);
+        assert_eq!(block.timestamp, 1_001);
+        assert!(block.nonce < u64::MAX);
+        assert_eq!(block.hash, block.calculate_hash());
+        assert!(meets_difficulty_bits(&block.hash, 8));
+    }
+
+    #[test]
+    fn changing_one_transaction_changes_the_merkle_root() {
+        let block = sample_block();
+        let mut transactions = block.transactions.clone();
+        transactions[1].amount = 11.0;
+        assert_ne!(merkle_root(&transactions), block.merkle_root);
+        assert!(block.merkle_root_matches());
+    }
+
+    #[test]
+    fn merkle_root_of_empty_single_and_odd_levels() {
+        assert_eq!(merkle_root(&[]), "0".repeat(64));
+        let mut transactions = sample_block().transactions;
+        assert_eq!(merkle_root(&transactions[..1]), transactions[0].witness_hash());
+        transactions.push(Transaction::new(String::from("0xbob"), String::from("0xcarol"), 5.0));
+        let mut padded = transactions.clone();
+        padded.push(transactions[2].clone());
+        assert_eq!(merkle_root(&transactions), merkle_root(&padded));
+    }
+
+    #[test]
+    fn merkle_proofs_lead_to_the_root() {
+        let mut transactions = sample_block().transactions;
+        for amount in [1.0, 2.0, 3.0] {
+            transactions.push(Transaction::new(String::from("0xbob"), String::from("0xcarol"), amount));
+        }
+        let root = merkle_root(&transactions);
+        for (index, transaction) in transactions.iter().enumerate() {
+            let proof = merkle_proof(&transactions, index).unwrap();
+            assert!(verify_merkle_proof(&transaction.witness_hash(), &proof, &root), "leaf {}", index);
+        }
+        assert!(merkle_proof(&transactions, transactions.len()).is_none());
+
+        let proof = merkle_proof(&transactions, 1).unwrap();
+        assert!(!verify_merkle_proof(&transactions[2].witness_hash(), &proof, &root));
+        let mut moved = proof.clone();
+        moved.index = 0;
+        assert!(!verify_merkle_proof(&transactions[1].witness_hash(), &moved, &root));
+    }
+}
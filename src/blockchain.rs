@@ -0,0 +1,2782 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::SigningKey;
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::block::{meets_difficulty_bits, merkle_proof, merkle_root, Block, MerkleProof, BLOCK_HEADER_BYTES};
+use crate::bloom::BloomFilter;
+use crate::clock::Clock;
+use crate::config::{BlockchainConfig, ValidationLevel};
+use crate::error::BmsError;
+use crate::format::{abbreviate_hash, format_amount};
+use crate::log::LogEvent;
+use crate::transaction::{address_of, Transaction, TransactionBuilder, TransactionKind, TxId, TxRejectReason};
+use crate::{MAX_MEMPOOL_PRESSURE, UNSTAKE_COOLDOWN_BLOCKS};
+
+/// Smallest capacity the transaction ID filter is ever sized for.
+const MIN_TX_FILTER_CAPACITY: usize = 1024;
+
+/// Version of the format `save_to_file` writes. Bump it whenever
+/// `SavedChain` changes, and teach `load_from_file` to migrate the old one.
+const SAVE_FORMAT_VERSION: u32 = 1;
+
+/// Marks a chain file written as `SaveFormat::Cbor`. JSON files start with
+/// `{`, so `load_from_file` can tell the two apart whatever the file name.
+const CBOR_MAGIC: &[u8] = b"BMS-CBOR";
+
+/// Marks a chain file written by `save_to_file_encrypted`. It is followed
+/// by the salt the key was derived with, the nonce, and the CBOR encoding
+/// sealed with ChaCha20-Poly1305.
+const ENCRYPTED_MAGIC: &[u8] = b"BMS-SEAL";
+
+/// Length of the random salt an encrypted file's key is derived with.
+const SALT_BYTES: usize = 16;
+
+/// Length of a ChaCha20-Poly1305 nonce.
+const NONCE_BYTES: usize = 12;
+
+/// Encoding of a chain file written by `save_to_file_as`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveFormat {
+    /// Pretty-printed JSON, readable and diffable.
+    Json,
+    /// CBOR after a short magic header, several times smaller than JSON.
+    Cbor,
+}
+
+impl SaveFormat {
+    /// `Cbor` for paths with a `.cbor` extension, `Json` for anything else.
+    pub fn for_path(path: &Path) -> SaveFormat {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("cbor") => SaveFormat::Cbor,
+            _ => SaveFormat::Json,
+        }
+    }
+}
+
+/// Outcome of [`Blockchain::merge_mempools`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MempoolMerge {
+    pub added: usize,
+    pub duplicates: usize,
+    pub rejected: usize,
+    /// Transactions left unchecked because the call had already admitted
+    /// `max_relay_batch` of them.
+    pub throttled: usize,
+}
+
+/// Reorgs `replace_chain` has carried out, as returned by
+/// [`Blockchain::reorg_stats`]. Only replacements that discard blocks count.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ReorgStats {
+    pub count: u32,
+    /// Most blocks discarded by a single reorg.
+    pub max_depth: u32,
+}
+
+/// What `save_to_file` writes. Balances and the mining reward follow from
+/// the chain; `load_from_file` replays it and checks they still agree.
+/// Amounts must come back bit for bit, since they are part of transaction
+/// IDs and signatures, hence serde_json's `float_roundtrip` feature; CBOR
+/// stores them exactly as is.
+#[derive(Serialize, Deserialize)]
+struct SavedChain {
+    /// `SAVE_FORMAT_VERSION` at the time of writing.
+    version: u32,
+    config: BlockchainConfig,
+    chain: Vec<Block>,
+    pending_transactions: Vec<Transaction>,
+    wallets: HashMap<String, f64>,
+    /// Signing keys of the wallets this chain created, by address. Stored
+    /// unencrypted: anyone who can read the file can spend from them.
+    keys: HashMap<String, [u8; 32]>,
+    current_mining_reward: f64,
+}
+
+/// Staked coins on their way back to the liquid balance.
+#[derive(Clone, Debug)]
+struct Unbonding {
+    address: String,
+    amount: f64,
+    release_height: u32,
+}
+
+/// Account state as of some block: what the chain tracks at its tip, and
+/// what validation replays a candidate chain into block by block.
+#[derive(Clone, Debug, Default)]
+struct Ledger {
+    wallets: HashMap<String, f64>,
+    /// Nonce each sender's next transaction must carry: one past its last
+    /// mined one.
+    nonces: HashMap<String, u64>,
+    stakes: HashMap<String, f64>,
+    unbonding: Vec<Unbonding>,
+    /// IDs of the non-coinbase transactions applied so far.
+    mined: HashSet<TxId>,
+}
+
+impl Ledger {
+    /// Applies a block's effects to balances, stakes and nonces, after first
+    /// releasing any unbonding stake that matures at its height.
+    fn apply_block(&mut self, block: &Block) {
+        let height = block.index;
+        let (released, still_bonding): (Vec<Unbonding>, Vec<Unbonding>) =
+            std::mem::take(&mut self.unbonding).into_iter().partition(|entry| entry.release_height <= height);
+        self.unbonding = still_bonding;
+        for entry in released {
+            *self.wallets.entry(entry.address).or_insert(0.0) += entry.amount;
+        }
+
+        for tx in &block.transactions {
+            if !tx.is_coinbase() {
+                *self.wallets.entry(tx.from.clone()).or_insert(0.0) -= tx.fee;
+            }
+            match tx.kind {
+                TransactionKind::Transfer => {
+                    if !tx.is_coinbase() {
+                        *self.wallets.entry(tx.from.clone()).or_insert(0.0) -= tx.amount;
+                    }
+                    *self.wallets.entry(tx.to.clone()).or_insert(0.0) += tx.amount;
+                }
+                TransactionKind::Stake => {
+                    *self.wallets.entry(tx.from.clone()).or_insert(0.0) -= tx.amount;
+                    *self.stakes.entry(tx.from.clone()).or_insert(0.0) += tx.amount;
+                }
+                TransactionKind::Unstake => {
+                    *self.stakes.entry(tx.from.clone()).or_insert(0.0) -= tx.amount;
+                    self.unbonding.push(Unbonding {
+                        address: tx.from.clone(),
+                        amount: tx.amount,
+                        release_height: height + UNSTAKE_COOLDOWN_BLOCKS,
+                    });
+                }
+            }
+            if !tx.is_coinbase() {
+                let next_nonce = self.nonces.entry(tx.from.clone()).or_insert(0);
+                *next_nonce = (*next_nonce).max(tx.nonce + 1);
+                self.mined.insert(tx.id());
+            }
+        }
+    }
+
+    /// Liquid coins `address` can spend in the block at `height`: its
+    /// balance plus any unbonding stake released at that height.
+    fn liquid_at(&self, address: &str, height: u32) -> f64 {
+        let released: f64 = self
+            .unbonding
+            .iter()
+            .filter(|entry| entry.address == address && entry.release_height <= height)
+            .map(|entry| entry.amount)
+            .sum();
+        self.wallets.get(address).unwrap_or(&0.0) + released
+    }
+}
+
+/// Admits the transactions of the block at `height` one at a time against
+/// the ledger at its parent. A sender's transactions must come in nonce
+/// order starting from its next nonce, and together cost no more than it
+/// can spend at that height; unstakes together no more than its stake.
+/// Coins received in the same block do not count, so the outcome does not
+/// depend on how the block orders different senders' transactions. Mining
+/// selects transactions and validation checks them with the same rules.
+struct BlockSpends<'a> {
+    ledger: &'a Ledger,
+    height: u32,
+    nonces: HashMap<String, u64>,
+    spent: HashMap<String, f64>,
+    unstaked: HashMap<String, f64>,
+}
+
+impl<'a> BlockSpends<'a> {
+    fn new(ledger: &'a Ledger, height: u32) -> BlockSpends<'a> {
+        BlockSpends { ledger, height, nonces: HashMap::new(), spent: HashMap::new(), unstaked: HashMap::new() }
+    }
+
+    /// Admits `tx` after those admitted before it, or says why it cannot
+    /// follow them. A rejected transaction changes nothing.
+    fn admit(&mut self, tx: &Transaction) -> Result<(), &'static str> {
+        let next_nonce = match self.nonces.get(&tx.from) {
+            Some(nonce) => *nonce,
+            None => *self.ledger.nonces.get(&tx.from).unwrap_or(&0),
+        };
+        if tx.nonce != next_nonce {
+            return Err("transaction nonce is not its sender's next one");
+        }
+        let spent = self.spent.get(&tx.from).unwrap_or(&0.0) + tx.liquid_cost();
+        if spent > self.ledger.liquid_at(&tx.from, self.height) {
+            return Err("transaction spends more than its sender's balance");
+        }
+        if tx.kind == TransactionKind::Unstake {
+            let unstaked = self.unstaked.get(&tx.from).unwrap_or(&0.0) + tx.amount;
+            if unstaked > *self.ledger.stakes.get(&tx.from).unwrap_or(&0.0) {
+                return Err("unstake exceeds its sender's stake");
+            }
+            self.unstaked.insert(tx.from.clone(), unstaked);
+        }
+        self.spent.insert(tx.from.clone(), spent);
+        self.nonces.insert(tx.from.clone(), next_nonce + 1);
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Blockchain {
+    chain: Vec<Block>,
+    pending_transactions: Vec<Transaction>,
+    /// Height at which each pending transaction entered the mempool.
+    pending_since: HashMap<TxId, u32>,
+    /// Transactions the most recent `mine_pending_transactions` evicted.
+    last_evicted: Vec<Transaction>,
+    ledger: Ledger,
+    /// Signing keys of the wallets created by `create_wallet`, by address.
+    keys: HashMap<String, SigningKey>,
+    tx_filter: BloomFilter,
+    /// Heights of the blocks touching each address, ascending; only kept
+    /// with `index_addresses`.
+    address_index: HashMap<String, Vec<u32>>,
+    /// Height of every block on the chain, by hash.
+    heights_by_hash: HashMap<String, u32>,
+    reorgs: ReorgStats,
+    config: BlockchainConfig,
+    clock: Clock,
+    rng: StdRng,
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Blockchain {
+    pub fn new() -> Blockchain {
+        Blockchain::build(BlockchainConfig::default(), Clock::System, StdRng::from_entropy())
+    }
+
+    /// Creates a chain from `config`, rejecting parameters that
+    /// `BlockchainBuilder::build` would also reject.
+    pub fn with_config(config: BlockchainConfig) -> Result<Blockchain, BmsError> {
+        config.check()?;
+        Ok(Blockchain::build(config, Clock::System, StdRng::from_entropy()))
+    }
+
+    /// Creates a chain driven by a manual clock and a seeded RNG, so the same
+    /// sequence of calls always yields the same addresses and block hashes.
+    pub fn deterministic(seed: u64) -> Blockchain {
+        Blockchain::build(BlockchainConfig::default(), Clock::manual(0, 1), StdRng::seed_from_u64(seed))
+    }
+
+    pub(crate) fn build(config: BlockchainConfig, clock: Clock, rng: StdRng) -> Blockchain {
+        let mut blockchain = Blockchain::without_blocks(config, clock, rng);
+        let timestamp = match blockchain.config.genesis_timestamp {
+            Some(timestamp) => timestamp,
+            None => blockchain.clock.now(),
+        };
+        blockchain.create_genesis_block(timestamp);
+        blockchain
+    }
+
+    /// A chain with no blocks at all, not even genesis; callers must put
+    /// one in place before handing it out.
+    fn without_blocks(config: BlockchainConfig, clock: Clock, rng: StdRng) -> Blockchain {
+        Blockchain {
+            chain: Vec::new(),
+            pending_transactions: Vec::new(),
+            pending_since: HashMap::new(),
+            last_evicted: Vec::new(),
+            ledger: Ledger::default(),
+            keys: HashMap::new(),
+            tx_filter: BloomFilter::with_capacity(MIN_TX_FILTER_CAPACITY),
+            address_index: HashMap::new(),
+            heights_by_hash: HashMap::new(),
+            reorgs: ReorgStats::default(),
+            config,
+            clock,
+            rng,
+        }
+    }
+
+    /// Mines the genesis block with one coinbase per configured genesis
+    /// allocation and derives all state from it.
+    fn create_genesis_block(&mut self, timestamp: i64) {
+        let allocations = self
+            .config
+            .genesis_alloc
+            .iter()
+            .enumerate()
+            .map(|(nonce, (address, amount))| Transaction::coinbase(address.clone(), *amount, nonce as u64))
+            .collect();
+        let genesis_block = self.seal_block(0, timestamp, allocations, String::from("0"));
+        self.chain = vec![genesis_block];
+        self.rebuild_state();
+    }
+
+    /// Credits `address` with `amount` through a genesis allocation. Only
+    /// possible while the chain holds nothing but the genesis block and the
+    /// mempool is empty; the genesis block is re-mined with the allocation.
+    /// Like allocations given to the builder, `amount` must be positive.
+    pub fn fund_genesis(&mut self, address: &str, amount: f64) -> Result<(), BmsError> {
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(BmsError::InvalidConfig(String::from("genesis allocations must be positive amounts")));
+        }
+        if self.chain.len() != 1 || !self.pending_transactions.is_empty() {
+            return Err(BmsError::GenesisSealed);
+        }
+        self.config.genesis_alloc.push((address.to_string(), amount));
+        self.create_genesis_block(self.chain[0].timestamp);
+        Ok(())
+    }
+
+    /// Generates a key pair and returns the address it owns. The chain keeps
+    /// the signing key so `sign_with_wallet` can sign for the address.
+    pub fn create_wallet(&mut self) -> String {
+        // Redraw on collision so an existing wallet's balance is never reset.
+        let (address, signing_key) = loop {
+            let signing_key = SigningKey::generate(&mut self.rng);
+            let candidate = address_of(signing_key.verifying_key().as_bytes());
+            if !self.ledger.wallets.contains_key(&candidate) {
+                break (candidate, signing_key);
+            }
+        };
+        self.ledger.wallets.insert(address.clone(), 0.0);
+        self.keys.insert(address.clone(), signing_key);
+        address
+    }
+
+    /// Signs `transaction` with the key of its sender, if that wallet was
+    /// created by this chain. Returns whether it was signed.
+    pub fn sign_with_wallet(&self, transaction: &mut Transaction) -> bool {
+        match self.keys.get(&transaction.from) {
+            Some(signing_key) => {
+                transaction.sign(signing_key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_balance(&self, address: &str) -> f64 {
+        *self.ledger.wallets.get(address).unwrap_or(&0.0)
+    }
+
+    /// Sum of the liquid balance that `address` has committed to pending
+    /// transactions: amounts and fees of transfers and stakes, and the fees
+    /// of unstakes, whose amounts draw on the stake instead.
+    pub fn pending_outgoing(&self, address: &str) -> f64 {
+        self.pending_transactions.iter().filter(|tx| tx.from == address).map(Transaction::liquid_cost).sum()
+    }
+
+    /// The confirmed balance minus whatever is already committed to pending
+    /// transactions, never below zero.
+    pub fn spendable_balance(&self, address: &str) -> f64 {
+        (self.get_balance(address) - self.pending_outgoing(address)).max(0.0)
+    }
+
+    /// The balance counting only credits with at least `min_confirmations`
+    /// confirmations, for callers that will not rely on shallow funds. The
+    /// rule is deliberately asymmetric: credits wait until they are buried
+    /// deep enough, debits count as soon as they are mined. The result is
+    /// therefore never above `get_balance` and never below zero.
+    pub fn confirmed_balance(&self, address: &str, min_confirmations: u32) -> f64 {
+        let height = self.height();
+        // Blocks from this index on have fewer than `min_confirmations`.
+        let first_shallow = (height + 2).saturating_sub(min_confirmations);
+        let mut unconfirmed_credits = 0.0;
+        for block in &self.chain {
+            for tx in &block.transactions {
+                if tx.to == address && tx.kind == TransactionKind::Transfer && block.index >= first_shallow {
+                    unconfirmed_credits += tx.amount;
+                }
+                if tx.from == address && tx.kind == TransactionKind::Unstake {
+                    // The unbonded coins were credited at their release height.
+                    let release_height = block.index + UNSTAKE_COOLDOWN_BLOCKS;
+                    if release_height <= height && release_height >= first_shallow {
+                        unconfirmed_credits += tx.amount;
+                    }
+                }
+            }
+        }
+        (self.get_balance(address) - unconfirmed_credits).max(0.0)
+    }
+
+    pub fn pending_transactions(&self) -> &[Transaction] {
+        &self.pending_transactions
+    }
+
+    /// How full the mempool is relative to one block: pending bytes divided
+    /// by the bytes a block has left for transactions after its header and
+    /// coinbase. 1.0 means the pending transactions exactly fill the next
+    /// block; above 1.0 some will have to wait. Without `max_block_bytes`
+    /// every pending transaction always fits, so the pressure is 0.0. The
+    /// result is clamped to `0.0..=MAX_MEMPOOL_PRESSURE`.
+    pub fn mempool_pressure(&self) -> f64 {
+        let max_block_bytes = match self.config.max_block_bytes {
+            Some(max_block_bytes) => max_block_bytes,
+            None => return 0.0,
+        };
+        let pending_bytes: usize = self.pending_transactions.iter().map(Transaction::serialized_size).sum();
+        if pending_bytes == 0 {
+            return 0.0;
+        }
+        let coinbase_bytes: usize = self
+            .coinbase_transactions(self.height() + 1, "", None, 0.0)
+            .iter()
+            .map(Transaction::serialized_size)
+            .sum();
+        let capacity = max_block_bytes.saturating_sub(BLOCK_HEADER_BYTES + coinbase_bytes);
+        if capacity == 0 {
+            return MAX_MEMPOOL_PRESSURE;
+        }
+        (pending_bytes as f64 / capacity as f64).min(MAX_MEMPOOL_PRESSURE)
+    }
+
+    /// Number of accounts this node tracks balances for.
+    pub fn wallet_count(&self) -> usize {
+        self.ledger.wallets.len()
+    }
+
+    /// Coins `address` has locked as stake. Coins that are unbonding after an
+    /// unstake are no longer counted here.
+    pub fn staked_balance(&self, address: &str) -> f64 {
+        *self.ledger.stakes.get(address).unwrap_or(&0.0)
+    }
+
+    pub fn total_staked(&self) -> f64 {
+        self.ledger.stakes.values().sum()
+    }
+
+    /// Unstaked coins still waiting out their cooldown, across all addresses.
+    pub fn total_unbonding(&self) -> f64 {
+        self.ledger.unbonding.iter().map(|entry| entry.amount).sum()
+    }
+
+    /// Addresses whose signing keys this chain holds, sorted.
+    pub fn wallet_addresses(&self) -> Vec<String> {
+        let mut addresses: Vec<String> = self.keys.keys().cloned().collect();
+        addresses.sort();
+        addresses
+    }
+
+    /// Sum of every address's liquid balance.
+    pub fn total_liquid(&self) -> f64 {
+        self.ledger.wallets.values().sum()
+    }
+
+    pub fn config(&self) -> &BlockchainConfig {
+        &self.config
+    }
+
+    /// Turns the `verbose` output on or off. Unlike the rest of the
+    /// configuration it has no bearing on the chain, so it can change at
+    /// any time.
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.config.verbose = verbose;
+    }
+
+    pub fn genesis_hash(&self) -> &str {
+        &self.chain[0].hash
+    }
+
+    /// Identifies this chain as the hash of its genesis block combined with
+    /// the configured network ID; nodes only sync when these match.
+    pub fn chain_id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.genesis_hash().as_bytes());
+        hasher.update(self.config.network_id.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn height(&self) -> u32 {
+        self.chain.len() as u32 - 1
+    }
+
+    pub fn latest_block(&self) -> &Block {
+        self.chain.last().unwrap()
+    }
+
+    /// Height of the block with `hash`, if it is on the chain.
+    pub fn height_of_hash(&self, hash: &str) -> Option<u32> {
+        self.heights_by_hash.get(hash).copied()
+    }
+
+    pub fn get_block_by_hash(&self, hash: &str) -> Option<&Block> {
+        self.height_of_hash(hash).map(|height| &self.chain[height as usize])
+    }
+
+    /// Subsidy the next mined block will pay.
+    pub fn current_mining_reward(&self) -> f64 {
+        self.reward_at_height(self.height() + 1)
+    }
+
+    /// Block subsidy for the block at `height`, halved every
+    /// `halving_interval` blocks and scaled down during the configured
+    /// reward ramp.
+    pub fn reward_at_height(&self, height: u32) -> f64 {
+        let halvings = height / self.config.halving_interval;
+        let scheduled = self.config.mining_reward / 2f64.powi(halvings as i32);
+        match self.config.reward_ramp {
+            Some(ramp) if height < ramp.blocks => {
+                let progress = height as f64 / ramp.blocks as f64;
+                scheduled * (ramp.start_fraction + (1.0 - ramp.start_fraction) * progress)
+            }
+            _ => scheduled,
+        }
+    }
+
+    /// Height of the first block past the tip to pay a halved subsidy.
+    pub fn next_halving_height(&self) -> u32 {
+        let interval = self.config.halving_interval;
+        (self.height() / interval).saturating_add(1).saturating_mul(interval)
+    }
+
+    pub fn blocks_until_next_halving(&self) -> u32 {
+        self.next_halving_height() - self.height()
+    }
+
+    /// The next `count` halvings as `(height, reward)` pairs, where `reward`
+    /// is the subsidy paid from that height on.
+    pub fn halving_schedule(&self, count: usize) -> Vec<(u32, f64)> {
+        let interval = self.config.halving_interval;
+        std::iter::successors(Some(self.next_halving_height()), |height| height.checked_add(interval))
+            .take(count)
+            .map(|height| (height, self.reward_at_height(height)))
+            .collect()
+    }
+
+    /// How a subsidy is split between the treasury (if configured) and the
+    /// miner: `(treasury_share, miner_share)`.
+    pub(crate) fn split_subsidy(&self, subsidy: f64) -> (f64, f64) {
+        match &self.config.treasury {
+            Some((_, fraction)) => {
+                let treasury_share = subsidy * fraction;
+                (treasury_share, subsidy - treasury_share)
+            }
+            None => (0.0, subsidy),
+        }
+    }
+
+    /// The nonce the next transaction from `address` must carry: one past
+    /// its highest pending nonce, or one past its last mined nonce if
+    /// nothing is pending.
+    pub fn next_nonce(&self, address: &str) -> u64 {
+        let confirmed = *self.ledger.nonces.get(address).unwrap_or(&0);
+        self.pending_transactions
+            .iter()
+            .filter(|tx| tx.from == address)
+            .map(|tx| tx.nonce + 1)
+            .fold(confirmed, u64::max)
+    }
+
+    /// A transfer builder tagged for this chain, with the nonce
+    /// `add_transaction` expects from `from` right now.
+    pub fn new_transaction(&self, from: &str, to: &str, amount: f64) -> TransactionBuilder {
+        Transaction::builder(from.to_string(), to.to_string(), amount)
+            .nonce(self.next_nonce(from))
+            .chain_tag(self.chain_id())
+    }
+
+    pub fn get_mempool_transaction(&self, tx_id: &str) -> Option<&Transaction> {
+        self.pending_transactions.iter().find(|tx| tx.id() == tx_id)
+    }
+
+    /// Withdraws a transaction that has not been mined yet on behalf of its
+    /// sender, who proves control of the sending address with
+    /// `signing_key`. Returns whether it was pending; mined transactions are
+    /// never affected. Fails with `InvalidSignature`, leaving the mempool
+    /// alone, if the key does not own the sender's address.
+    pub fn cancel_pending(&mut self, tx_id: &str, signing_key: &SigningKey) -> Result<bool, TxRejectReason> {
+        let Some(transaction) = self.get_mempool_transaction(tx_id) else {
+            return Ok(false);
+        };
+        if address_of(signing_key.verifying_key().as_bytes()) != transaction.from {
+            return Err(TxRejectReason::InvalidSignature);
+        }
+        Ok(self.remove_pending_transaction(tx_id).is_some())
+    }
+
+    /// Like `cancel_pending`, with the key of the sender's wallet if this
+    /// chain created it. Fails with `InvalidSignature` if it holds no key
+    /// for the sender.
+    pub fn cancel_with_wallet(&mut self, tx_id: &str) -> Result<bool, TxRejectReason> {
+        let Some(transaction) = self.get_mempool_transaction(tx_id) else {
+            return Ok(false);
+        };
+        let Some(signing_key) = self.keys.get(&transaction.from).cloned() else {
+            return Err(TxRejectReason::InvalidSignature);
+        };
+        self.cancel_pending(tx_id, &signing_key)
+    }
+
+    /// Removes a still-pending transaction from the mempool and returns it.
+    /// Later transactions from the same sender can no longer be mined
+    /// without its nonce, so they are dropped along with it.
+    pub fn remove_pending_transaction(&mut self, tx_id: &str) -> Option<Transaction> {
+        let position = self.pending_transactions.iter().position(|tx| tx.id() == tx_id)?;
+        let transaction = self.pending_transactions.remove(position);
+        self.reconcile_mempool(Vec::new());
+        Some(transaction)
+    }
+
+    /// Queues a signed transaction. Its nonce must be the sender's
+    /// `next_nonce`, so a signed transaction cannot be replayed.
+    pub fn add_transaction(&mut self, transaction: Transaction) -> Result<TxId, TxRejectReason> {
+        let from = transaction.from.clone();
+        match self.queue_transaction(transaction) {
+            Ok(tx_id) => {
+                self.log(LogEvent::TxAccepted { tx_id: &tx_id });
+                Ok(tx_id)
+            }
+            Err(reason) => {
+                self.log(LogEvent::TxRejected { from: &from, reason: reason.to_string() });
+                Err(reason)
+            }
+        }
+    }
+
+    /// `add_transaction` without the logging.
+    fn queue_transaction(&mut self, transaction: Transaction) -> Result<TxId, TxRejectReason> {
+        if self.mempool_is_full() {
+            return Err(TxRejectReason::MempoolFull);
+        }
+        self.check_transaction(&transaction)?;
+        let expected = self.next_nonce(&transaction.from);
+        if transaction.nonce != expected {
+            return Err(TxRejectReason::WrongNonce { expected });
+        }
+        let tx_id = transaction.id();
+        self.pending_since.insert(tx_id.clone(), self.height());
+        self.pending_transactions.push(transaction);
+        Ok(tx_id)
+    }
+
+    fn mempool_is_full(&self) -> bool {
+        matches!(self.config.max_mempool_size, Some(max) if self.pending_transactions.len() >= max)
+    }
+
+    /// Absorbs transactions relayed by a peer, taken in nonce order. As with
+    /// `add_transaction`, each must carry its sender's next nonce.
+    /// Transactions that are already pending or mined count as duplicates;
+    /// those the current state rejects, or that arrive once the mempool is
+    /// full, count as rejected. Once `max_relay_batch` have been added, the
+    /// rest count as throttled; the peer may relay them again later.
+    pub fn merge_mempools(&mut self, other: &[Transaction]) -> MempoolMerge {
+        let mut merge = MempoolMerge::default();
+        let mut pending: HashSet<TxId> = self.pending_transactions.iter().map(Transaction::id).collect();
+        let mut relayed: Vec<&Transaction> = other.iter().collect();
+        relayed.sort_by_key(|tx| tx.nonce);
+        for tx in relayed {
+            if self.config.max_relay_batch.is_some_and(|max| merge.added >= max) {
+                merge.throttled += 1;
+                continue;
+            }
+            let tx_id = tx.id();
+            if pending.contains(&tx_id) || self.contains_tx(&tx_id) {
+                merge.duplicates += 1;
+            } else if self.mempool_is_full() || self.check_transaction(tx).is_err() || tx.nonce != self.next_nonce(&tx.from) {
+                merge.rejected += 1;
+            } else {
+                pending.insert(tx_id.clone());
+                self.pending_since.insert(tx_id, self.height());
+                self.pending_transactions.push(tx.clone());
+                merge.added += 1;
+            }
+        }
+        merge
+    }
+
+    fn memo_fits(&self, transaction: &Transaction) -> bool {
+        transaction.memo.as_ref().is_none_or(|memo| memo.len() <= self.config.max_memo_bytes)
+    }
+
+    /// Checks `transaction` against the current state and mempool without
+    /// queuing it.
+    fn check_transaction(&self, transaction: &Transaction) -> Result<(), TxRejectReason> {
+        if transaction.is_coinbase() {
+            return Err(TxRejectReason::CoinbaseNotAllowed);
+        }
+        if !transaction.verify() {
+            return Err(TxRejectReason::InvalidSignature);
+        }
+        if !transaction.amount.is_finite() || transaction.amount <= 0.0 {
+            return Err(TxRejectReason::InvalidAmount);
+        }
+        if !transaction.fee.is_finite() || transaction.fee < 0.0 {
+            return Err(TxRejectReason::InvalidFee);
+        }
+        if transaction.fee < self.config.min_relay_fee {
+            return Err(TxRejectReason::FeeTooLow { min: self.config.min_relay_fee });
+        }
+        if transaction.chain_tag != self.chain_id() {
+            return Err(TxRejectReason::WrongChain);
+        }
+        if !self.memo_fits(transaction) {
+            return Err(TxRejectReason::MemoTooLong { max: self.config.max_memo_bytes });
+        }
+        // The next block can be stamped no earlier than the tip.
+        if transaction.is_expired_at(self.latest_block().timestamp) {
+            return Err(TxRejectReason::Expired);
+        }
+        match transaction.kind {
+            TransactionKind::Transfer => {
+                if transaction.from == transaction.to {
+                    return Err(TxRejectReason::SelfTransfer);
+                }
+                if transaction.amount < self.config.dust_threshold {
+                    return Err(TxRejectReason::Dust { threshold: self.config.dust_threshold });
+                }
+                if let Some(max) = self.config.max_tx_amount {
+                    if transaction.amount > max {
+                        return Err(TxRejectReason::AboveMaxAmount { max });
+                    }
+                }
+                // Pending transactions have already claimed part of the
+                // balance; a transaction may only spend what is left.
+                let spendable = self.spendable_balance(&transaction.from);
+                if spendable < transaction.liquid_cost() {
+                    let staked = self.staked_balance(&transaction.from);
+                    if spendable + staked >= transaction.liquid_cost() {
+                        return Err(TxRejectReason::FundsStaked);
+                    }
+                    return Err(TxRejectReason::InsufficientBalance);
+                }
+            }
+            TransactionKind::Stake => {
+                if self.spendable_balance(&transaction.from) < transaction.liquid_cost() {
+                    return Err(TxRejectReason::InsufficientBalance);
+                }
+            }
+            TransactionKind::Unstake => {
+                if self.spendable_balance(&transaction.from) < transaction.liquid_cost() {
+                    return Err(TxRejectReason::InsufficientBalance);
+                }
+                let pending_unstakes: f64 = self
+                    .pending_transactions
+                    .iter()
+                    .filter(|tx| tx.kind == TransactionKind::Unstake && tx.from == transaction.from)
+                    .map(|tx| tx.amount)
+                    .sum();
+                if self.staked_balance(&transaction.from) - pending_unstakes < transaction.amount {
+                    return Err(TxRejectReason::InsufficientStake);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues a transfer of everything `from` can currently spend to `to`,
+    /// signed with the wallet's key. The transfer pays the minimum relay fee
+    /// out of the swept balance, so `to` receives the balance less the fee.
+    /// Fails with `SweepTooSmall` if what is left after the fee would not
+    /// clear the dust threshold, and `InvalidSignature` if this chain holds
+    /// no key for `from`.
+    pub fn sweep(&mut self, from: &str, to: &str) -> Result<TxId, TxRejectReason> {
+        let spendable = self.spendable_balance(from);
+        let fee = self.config.min_relay_fee;
+        let mut amount = spendable - fee;
+        // The balance check adds the fee back; keep that sum within reach.
+        while amount > 0.0 && amount + fee > spendable {
+            amount = amount.next_down();
+        }
+        if amount <= 0.0 || amount < self.config.dust_threshold {
+            return Err(TxRejectReason::SweepTooSmall { fee, threshold: self.config.dust_threshold });
+        }
+        let mut transaction = self.new_transaction(from, to, amount).fee(fee).build();
+        if !self.sign_with_wallet(&mut transaction) {
+            return Err(TxRejectReason::InvalidSignature);
+        }
+        self.add_transaction(transaction)
+    }
+
+    /// Queues `transactions` only if every one of them is accepted. They
+    /// are checked in batch order with the ones before them in place, so
+    /// each sender's spendable balance must cover the sum of its transfers
+    /// in the batch, and its transactions need consecutive nonces. On any
+    /// rejection the mempool is left exactly as it was and nothing is
+    /// logged; otherwise each transaction is logged as accepted.
+    pub fn add_transactions_atomic(&mut self, transactions: Vec<Transaction>) -> Result<Vec<TxId>, TxRejectReason> {
+        let snapshot = self.pending_transactions.len();
+        let mut tx_ids = Vec::with_capacity(transactions.len());
+        for transaction in transactions {
+            match self.queue_transaction(transaction) {
+                Ok(tx_id) => tx_ids.push(tx_id),
+                Err(reason) => {
+                    self.pending_transactions.truncate(snapshot);
+                    for tx_id in &tx_ids {
+                        self.pending_since.remove(tx_id);
+                    }
+                    return Err(reason);
+                }
+            }
+        }
+        for tx_id in &tx_ids {
+            self.log(LogEvent::TxAccepted { tx_id });
+        }
+        Ok(tx_ids)
+    }
+
+    /// Number of confirmations for `tx_id`: `None` if the transaction is
+    /// unknown, `Some(0)` while it is still pending, and otherwise one for the
+    /// block that includes it plus one for every block mined on top of it.
+    pub fn confirmations(&self, tx_id: &str) -> Option<u32> {
+        if self.pending_transactions.iter().any(|tx| tx.id() == tx_id) {
+            return Some(0);
+        }
+        self.chain
+            .iter()
+            .find(|block| block.transactions.iter().any(|tx| tx.id() == tx_id))
+            .map(|block| self.height() - block.index + 1)
+    }
+
+    /// Fast membership test against the IDs of every mined transaction.
+    /// `false` is definite; `true` may be a false positive (about 1% at the
+    /// filter's sized capacity), so confirm with `contains_tx`.
+    pub fn probably_contains_tx(&self, tx_id: &str) -> bool {
+        self.tx_filter.probably_contains(tx_id)
+    }
+
+    /// Whether `tx_id` is mined, consulting the Bloom filter first and only
+    /// scanning the chain when it reports a possible match.
+    pub fn contains_tx(&self, tx_id: &str) -> bool {
+        self.probably_contains_tx(tx_id)
+            && self.chain.iter().any(|block| block.transactions.iter().any(|tx| tx.id() == tx_id))
+    }
+
+    /// Resizes the filter to twice the current transaction count and
+    /// re-inserts every mined transaction ID.
+    fn rebuild_tx_filter(&mut self) {
+        let tx_count: usize = self.chain.iter().map(|block| block.transactions.len()).sum();
+        let mut filter = BloomFilter::with_capacity((tx_count * 2).max(MIN_TX_FILTER_CAPACITY));
+        for tx in self.chain.iter().flat_map(|block| &block.transactions) {
+            filter.insert(&tx.id());
+        }
+        self.tx_filter = filter;
+    }
+
+    /// Whether `tx_id` is mined and has at least `depth` confirmations.
+    pub fn is_confirmed(&self, tx_id: &str, depth: u32) -> bool {
+        matches!(self.confirmations(tx_id), Some(confirmations) if confirmations > 0 && confirmations >= depth)
+    }
+
+    /// The height of the block holding mined transaction `tx_id` and a
+    /// proof that the block's `merkle_root` commits to it, checked with
+    /// `verify_merkle_proof` against the transaction's `witness_hash`.
+    /// `None` if the transaction is pending or unknown.
+    pub fn verify_transaction_in_chain(&self, tx_id: &str) -> Option<(u32, MerkleProof)> {
+        if !self.probably_contains_tx(tx_id) {
+            return None;
+        }
+        self.chain.iter().find_map(|block| {
+            let index = block.transactions.iter().position(|tx| tx.id() == tx_id)?;
+            Some((block.index, merkle_proof(&block.transactions, index)?))
+        })
+    }
+
+    /// Mines the pending transactions that fit in the block. Transactions
+    /// are taken in block order, highest fee rate first, as long as the
+    /// block stays within `max_block_bytes`, if set, and they pass the same
+    /// state checks as `try_append_block`; the rest stay pending for a
+    /// later block.
+    /// Transactions whose `expires_at` has passed by the block's timestamp
+    /// are dropped, and afterwards those that have been pending for
+    /// `mempool_expiry_blocks` or more are evicted; see `last_evicted`.
+    /// Returns the newly mined block, or `BmsError::NothingToMine` without
+    /// mining if the mempool is empty and `allow_empty_blocks` is off.
+    pub fn mine_pending_transactions(&mut self, miner_address: &str) -> Result<&Block, BmsError> {
+        self.mine_pending(miner_address, None)
+    }
+
+    /// Like `mine_pending_transactions`, but embeds `message` in the
+    /// miner's coinbase memo. Fails without mining if the message is longer
+    /// than `max_memo_bytes`.
+    pub fn mine_pending_transactions_with_message(&mut self, miner_address: &str, message: &str) -> Result<&Block, BmsError> {
+        if message.len() > self.config.max_memo_bytes {
+            return Err(TxRejectReason::MemoTooLong { max: self.config.max_memo_bytes }.into());
+        }
+        self.mine_pending(miner_address, Some(message.to_string()))
+    }
+
+    fn mine_pending(&mut self, miner_address: &str, message: Option<String>) -> Result<&Block, BmsError> {
+        if !self.config.allow_empty_blocks && self.pending_transactions.is_empty() {
+            return Err(BmsError::NothingToMine);
+        }
+        let index = self.chain.len() as u32;
+        let timestamp = self.next_block_timestamp();
+        let (expired, pending): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.pending_transactions).into_iter().partition(|tx| tx.is_expired_at(timestamp));
+        // Fees only change the coinbase amount, not its size.
+        let coinbase = self.coinbase_transactions(index, miner_address, message.clone(), 0.0);
+        let (transactions_to_mine, waiting) = self.select_transactions_for_block(pending, &coinbase);
+        self.pending_transactions = waiting;
+        self.mine_block(transactions_to_mine, miner_address, message, timestamp);
+        self.last_evicted = expired;
+        let evicted = self.evict_expired();
+        self.last_evicted.extend(evicted);
+        let stranded = self.reconcile_mempool(Vec::new());
+        self.last_evicted.extend(stranded);
+        Ok(self.latest_block())
+    }
+
+    /// Splits `pending` into the transactions the next block takes and the
+    /// ones left waiting. Transactions are taken in block order as long as
+    /// the block, with `coinbase` in it, stays within `max_block_bytes` and
+    /// they pass the state checks against the transactions taken before them.
+    fn select_transactions_for_block(
+        &self,
+        pending: Vec<Transaction>,
+        coinbase: &[Transaction],
+    ) -> (Vec<Transaction>, Vec<Transaction>) {
+        let mut used = BLOCK_HEADER_BYTES + coinbase.iter().map(Transaction::serialized_size).sum::<usize>();
+        let mut spends = BlockSpends::new(&self.ledger, self.chain.len() as u32);
+        let mut waiting = into_block_order(pending);
+        let mut selected = Vec::new();
+        // A transaction passed over for its nonce may fit once an earlier
+        // one from its sender is in, so go round until nothing changes.
+        loop {
+            let before = selected.len();
+            let mut deferred = Vec::new();
+            for tx in waiting {
+                let size = tx.serialized_size();
+                let fits = self.config.max_block_bytes.is_none_or(|max_block_bytes| used + size <= max_block_bytes);
+                if fits && spends.admit(&tx).is_ok() {
+                    used += size;
+                    selected.push(tx);
+                } else {
+                    deferred.push(tx);
+                }
+            }
+            waiting = deferred;
+            if selected.len() == before {
+                return (selected, waiting);
+            }
+        }
+    }
+
+    /// The merkle root the block `mine_pending_transactions(miner_address)`
+    /// would mine next has, if nothing changes before then: the same
+    /// coinbase and the same selection from the mempool. Nothing is mined
+    /// and the clock is left untouched. With an empty mempool this is the
+    /// root of a coinbase-only block, whether or not empty blocks are allowed.
+    pub fn preview_next_merkle_root(&self, miner_address: &str) -> String {
+        let index = self.chain.len() as u32;
+        let timestamp = self.clock.clone().now().max(self.latest_block().timestamp);
+        let pending = self.pending_transactions.iter().filter(|tx| !tx.is_expired_at(timestamp)).cloned().collect();
+        let coinbase = self.coinbase_transactions(index, miner_address, None, 0.0);
+        let (selected, _) = self.select_transactions_for_block(pending, &coinbase);
+        merkle_root(&self.block_transactions(selected, miner_address, None))
+    }
+
+    /// Transactions evicted by the most recent call to
+    /// `mine_pending_transactions`: those that expired, and those left
+    /// without a minable nonce or balance once they were gone.
+    pub fn last_evicted(&self) -> &[Transaction] {
+        &self.last_evicted
+    }
+
+    /// Drops pending transactions that entered the mempool at least
+    /// `mempool_expiry_blocks` blocks ago. Transactions returned to the
+    /// mempool by an undo or a reorg count as entering at the current height;
+    /// ones that entered above it, before blocks were undone, as entering now.
+    fn evict_expired(&mut self) -> Vec<Transaction> {
+        let height = self.height();
+        let pending_ids: Vec<TxId> = self.pending_transactions.iter().map(Transaction::id).collect();
+        let mut since = HashMap::with_capacity(pending_ids.len());
+        for tx_id in &pending_ids {
+            let entered = self.pending_since.get(tx_id).copied().unwrap_or(height);
+            since.insert(tx_id.clone(), entered);
+        }
+        self.pending_since = since;
+
+        let expiry = match self.config.mempool_expiry_blocks {
+            Some(expiry) => expiry,
+            None => return Vec::new(),
+        };
+        let pending = std::mem::take(&mut self.pending_transactions);
+        let (evicted, kept): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .zip(pending_ids)
+            .partition(|(_, tx_id)| height.saturating_sub(self.pending_since[tx_id]) >= expiry);
+        self.pending_transactions = kept.into_iter().map(|(tx, _)| tx).collect();
+        evicted
+            .into_iter()
+            .map(|(tx, tx_id)| {
+                self.pending_since.remove(&tx_id);
+                tx
+            })
+            .collect()
+    }
+
+    /// Mines empty blocks for `miner_address` until `height()` reaches
+    /// `target`, leaving the mempool untouched. This is an explicit request
+    /// for empty blocks, so `allow_empty_blocks` does not apply. Does nothing if the chain is
+    /// already at or past `target`.
+    pub fn mine_until_height(&mut self, target: u32, miner_address: &str) {
+        while self.height() < target {
+            let timestamp = self.next_block_timestamp();
+            self.mine_block(Vec::new(), miner_address, None, timestamp);
+        }
+    }
+
+    /// Timestamp for the next block from the clock. Never earlier than the
+    /// tip, even if the system clock steps backwards; `transactions_since`
+    /// relies on this.
+    fn next_block_timestamp(&mut self) -> i64 {
+        self.clock.now().max(self.latest_block().timestamp)
+    }
+
+    /// The transactions of the next block in canonical order: the miner's
+    /// reward coinbase first, then the treasury payout if one is configured,
+    /// then `transactions` in block order (see `block_order`).
+    fn block_transactions(&self, transactions: Vec<Transaction>, miner_address: &str, message: Option<String>) -> Vec<Transaction> {
+        let transactions = into_block_order(transactions);
+        let mut block_transactions =
+            self.coinbase_transactions(self.chain.len() as u32, miner_address, message, total_fees(&transactions));
+        block_transactions.extend(transactions);
+        block_transactions
+    }
+
+    /// Assembles the next block from `transactions` (see
+    /// `block_transactions`), mines it and appends it.
+    fn mine_block(
+        &mut self,
+        transactions: Vec<Transaction>,
+        miner_address: &str,
+        message: Option<String>,
+        timestamp: i64,
+    ) -> &Block {
+        let index = self.chain.len() as u32;
+        let previous_hash = self.latest_block().hash.clone();
+        let transactions_to_mine = self.block_transactions(transactions, miner_address, message);
+        let new_block = self.seal_block(index, timestamp, transactions_to_mine, previous_hash);
+        self.log(LogEvent::BlockMined { height: index, hash: &new_block.hash });
+        self.push_block(new_block);
+
+        if (self.chain.len() as u32).is_multiple_of(self.config.halving_interval) {
+            self.log(LogEvent::RewardHalved { reward: self.current_mining_reward() });
+        }
+        self.latest_block()
+    }
+
+    /// Mines a block holding the miner's coinbase and `transactions` and
+    /// returns it with the balances that would result from appending it,
+    /// leaving the chain, mempool and clock untouched. The transactions are
+    /// not checked; this previews their effect as given.
+    pub fn speculative_mine(&self, transactions: Vec<Transaction>, miner_address: &str) -> (Block, HashMap<String, f64>) {
+        let index = self.chain.len() as u32;
+        let timestamp = self.clock.clone().now().max(self.latest_block().timestamp);
+        let block_transactions = self.block_transactions(transactions, miner_address, None);
+        let block = self.seal_block(index, timestamp, block_transactions, self.latest_block().hash.clone());
+
+        let mut ledger = self.ledger.clone();
+        ledger.apply_block(&block);
+        (block, ledger.wallets)
+    }
+
+    /// Mines a block at `index` under the difficulty and proof-of-work
+    /// version in force at that height, on top of the chain's blocks below
+    /// `index`.
+    pub(crate) fn seal_block(&self, index: u32, timestamp: i64, transactions: Vec<Transaction>, previous_hash: String) -> Block {
+        let target_bits = self.target_bits_for(&self.chain[..index as usize], index);
+        let pow_version = self.config.pow_version_at(index);
+        Block::with_target_bits(index, timestamp, transactions, previous_hash, target_bits, pow_version)
+    }
+
+    /// Target, in leading zero bits, the next mined block has to meet.
+    pub fn next_target_bits(&self) -> usize {
+        self.target_bits_for(&self.chain, self.height() + 1)
+    }
+
+    /// Height of the next block at which the difficulty may retarget: the
+    /// first multiple of the retarget interval past the tip. `None` without
+    /// `retarget`.
+    pub fn next_retarget_height(&self) -> Option<u32> {
+        let interval = self.config.retarget?.interval;
+        Some((self.height() / interval).saturating_add(1).saturating_mul(interval))
+    }
+
+    pub fn blocks_until_next_retarget(&self) -> Option<u32> {
+        Some(self.next_retarget_height()? - self.height())
+    }
+
+    /// Target for the block at `height` on top of `history`, the blocks
+    /// before it. Without `retarget` this is the configured target. With it,
+    /// block 1 starts from the configured target, each later block inherits
+    /// its predecessor's, and at every multiple of the interval the target
+    /// gains a bit (up to `max_bits`) if the gaps between the last
+    /// `interval` blocks averaged under half the target block time, or loses
+    /// one (down to `min_bits`) if they averaged over twice that.
+    fn target_bits_for(&self, history: &[Block], height: u32) -> usize {
+        let configured = self.config.target_bits_at(height);
+        let (Some(retarget), Some(previous)) = (self.config.retarget, history.last()) else {
+            return configured;
+        };
+        if height == 1 {
+            return configured;
+        }
+        let inherited = previous.target_bits;
+        let interval = retarget.interval as usize;
+        if !(height as usize).is_multiple_of(interval) || history.len() < interval {
+            return inherited;
+        }
+        let window = &history[history.len() - interval..];
+        let elapsed = window[interval - 1].timestamp - window[0].timestamp;
+        let expected = (interval as i64 - 1) * retarget.target_block_time;
+        if elapsed * 2 < expected {
+            (inherited + 1).min(retarget.max_bits)
+        } else if elapsed > expected * 2 {
+            inherited.saturating_sub(1).max(retarget.min_bits)
+        } else {
+            inherited
+        }
+    }
+
+    /// Prints `event` in the configured `log_format` when `verbose` is on.
+    fn log(&self, event: LogEvent) {
+        if self.config.verbose {
+            println!("{}", event.render(self.config.log_format));
+        }
+    }
+
+    /// Applies an already validated block and makes it the new tip.
+    fn push_block(&mut self, block: Block) {
+        self.ledger.apply_block(&block);
+        for tx in &block.transactions {
+            self.tx_filter.insert(&tx.id());
+        }
+        self.index_block(&block);
+        self.heights_by_hash.insert(block.hash.clone(), block.index);
+        self.chain.push(block);
+        if self.tx_filter.is_saturated() {
+            self.rebuild_tx_filter();
+        }
+        self.debug_check_state();
+    }
+
+    /// Replays the chain into fresh balances, stakes, nonces and unbonding
+    /// entries and compares them with the tracked state, naming the first
+    /// address that differs.
+    pub fn verify_state_matches_chain(&self) -> Result<(), BmsError> {
+        let mut replayed = Ledger::default();
+        for block in &self.chain {
+            replayed.apply_block(block);
+        }
+        let Ledger { wallets, nonces, stakes, unbonding, .. } = replayed;
+
+        let desync = |address: &str| Err(BmsError::StateDesync { address: address.to_string() });
+        let same = |a: f64, b: f64| (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0);
+        for address in wallets.keys().chain(self.ledger.wallets.keys()) {
+            if !same(*wallets.get(address).unwrap_or(&0.0), self.get_balance(address)) {
+                return desync(address);
+            }
+        }
+        for address in stakes.keys().chain(self.ledger.stakes.keys()) {
+            if !same(*stakes.get(address).unwrap_or(&0.0), self.staked_balance(address)) {
+                return desync(address);
+            }
+        }
+        for address in nonces.keys().chain(self.ledger.nonces.keys()) {
+            if nonces.get(address) != self.ledger.nonces.get(address) {
+                return desync(address);
+            }
+        }
+        if unbonding.len() != self.ledger.unbonding.len() {
+            let address = unbonding.first().or(self.ledger.unbonding.first()).map_or("", |entry| entry.address.as_str());
+            return desync(address);
+        }
+        Ok(())
+    }
+
+    /// Asserts `verify_state_matches_chain`, and that the hash index covers
+    /// exactly the chain, after every state change in debug builds. Compiled
+    /// out in release builds, where the O(chain) replay would be too costly.
+    fn debug_check_state(&self) {
+        if cfg!(debug_assertions) {
+            if let Err(err) = self.verify_state_matches_chain() {
+                panic!("{}", err);
+            }
+            let hashes_indexed = self.heights_by_hash.len() == self.chain.len()
+                && self.chain.iter().all(|block| self.height_of_hash(&block.hash) == Some(block.index));
+            assert!(hashes_indexed, "hash to height index does not match the chain");
+        }
+    }
+
+    /// The miner's reward followed by the treasury payout, if configured,
+    /// for the block at `index`. `message` becomes the reward's memo.
+    fn coinbase_transactions(&self, index: u32, miner_address: &str, message: Option<String>, fees: f64) -> Vec<Transaction> {
+        let (treasury_share, miner_share) = self.split_subsidy(self.reward_at_height(index));
+        let mut reward = Transaction::coinbase(miner_address.to_string(), miner_share + fees, index as u64);
+        reward.memo = message;
+        let mut coinbase = vec![reward];
+        if let Some((treasury_address, _)) = &self.config.treasury {
+            coinbase.push(Transaction::coinbase(treasury_address.clone(), treasury_share, index as u64));
+        }
+        coinbase
+    }
+
+    pub fn is_chain_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Like `is_chain_valid`, but reports the first offending block and why.
+    pub fn validate(&self) -> Result<(), BmsError> {
+        self.validate_chain(&self.chain)
+    }
+
+    /// Validates `chain` from genesis, replaying each block into a ledger
+    /// so the next one is checked against the state at its parent.
+    pub(crate) fn validate_chain(&self, chain: &[Block]) -> Result<(), BmsError> {
+        let mut ledger = Ledger::default();
+        if let Some(genesis) = chain.first() {
+            self.validate_genesis(genesis)?;
+            ledger.apply_block(genesis);
+        }
+        for height in 1..chain.len() {
+            self.validate_block(&chain[height], &chain[..height], &ledger)?;
+            ledger.apply_block(&chain[height]);
+        }
+        Ok(())
+    }
+
+    /// The genesis block follows its own rules instead of `validate_block`'s:
+    /// it sits at index 0, links to the placeholder hash `"0"`, carries valid
+    /// proof of work at the genesis difficulty, and holds exactly the
+    /// configured genesis allocations as coinbase transfers, in order. It
+    /// pays no block reward.
+    fn validate_genesis(&self, genesis: &Block) -> Result<(), BmsError> {
+        if genesis.index != 0 {
+            return Err(BmsError::invalid_block(0, "index does not match its position"));
+        }
+        if genesis.previous_hash != "0" {
+            return Err(BmsError::invalid_block(0, "genesis block does not link to the placeholder hash"));
+        }
+        if genesis.hash != genesis.calculate_hash() {
+            return Err(BmsError::invalid_block(0, "stored hash does not match its contents"));
+        }
+        if !genesis.merkle_root_matches() {
+            return Err(BmsError::invalid_block(0, "merkle root does not match its transactions"));
+        }
+        if genesis.pow_version != self.config.pow_version_at(0) {
+            return Err(BmsError::invalid_block(0, "mined under the wrong proof-of-work version"));
+        }
+        let matches_allocations = genesis.transactions.len() == self.config.genesis_alloc.len()
+            && genesis.transactions.iter().zip(&self.config.genesis_alloc).enumerate().all(|(nonce, (tx, (address, amount)))| {
+                tx.is_coinbase() && tx.kind == TransactionKind::Transfer && tx.nonce == nonce as u64 && &tx.to == address && tx.amount == *amount
+            });
+        if !matches_allocations {
+            return Err(BmsError::invalid_block(0, "transactions do not match the genesis allocations"));
+        }
+        if genesis.target_bits != self.config.target_bits_at(0) {
+            return Err(BmsError::invalid_block(0, "records the wrong difficulty target"));
+        }
+        if !meets_difficulty_bits(&genesis.hash, genesis.target_bits) {
+            return Err(BmsError::invalid_block(0, "hash does not meet the difficulty target"));
+        }
+        Ok(())
+    }
+
+    /// Checks `current_block` as the successor of `history`, the blocks
+    /// from genesis up to the one it builds on, with `ledger` the state
+    /// they leave behind. How much is checked depends on the configured
+    /// `ValidationLevel`.
+    fn validate_block(&self, current_block: &Block, history: &[Block], ledger: &Ledger) -> Result<(), BmsError> {
+        let previous_block = &history[history.len() - 1];
+        let height = previous_block.index + 1;
+
+        if current_block.index != height {
+            return Err(BmsError::invalid_block(height, "index does not match its position"));
+        }
+
+        if current_block.hash != current_block.calculate_hash() {
+            return Err(BmsError::invalid_block(height, "stored hash does not match its contents"));
+        }
+
+        if !current_block.merkle_root_matches() {
+            return Err(BmsError::invalid_block(height, "merkle root does not match its transactions"));
+        }
+
+        if current_block.previous_hash != previous_block.hash {
+            return Err(BmsError::invalid_block(height, "previous hash does not link to the prior block"));
+        }
+
+        if current_block.timestamp < previous_block.timestamp {
+            return Err(BmsError::invalid_block(height, "timestamp is earlier than the prior block"));
+        }
+
+        if current_block.pow_version != self.config.pow_version_at(height) {
+            return Err(BmsError::invalid_block(height, "mined under the wrong proof-of-work version"));
+        }
+
+        if current_block.target_bits != self.target_bits_for(history, height) {
+            return Err(BmsError::invalid_block(height, "records the wrong difficulty target"));
+        }
+
+        if !meets_difficulty_bits(&current_block.hash, current_block.target_bits) {
+            return Err(BmsError::invalid_block(height, "hash does not meet the difficulty target"));
+        }
+
+        if let Some(max_block_bytes) = self.config.max_block_bytes {
+            if current_block.size() > max_block_bytes {
+                return Err(BmsError::invalid_block(height, "block exceeds the maximum size"));
+            }
+        }
+
+        self.validate_coinbase(current_block)?;
+
+        if let Some(max) = self.config.max_block_coinbase {
+            let minted: f64 = current_block.transactions.iter().filter(|tx| tx.is_coinbase()).map(|tx| tx.amount).sum();
+            if minted > max {
+                let reason = format!("coinbase total {} exceeds the cap of {}", format_amount(minted), format_amount(max));
+                return Err(BmsError::invalid_block(height, &reason));
+            }
+        }
+
+        if current_block.transactions.iter().any(|tx| !tx.amount.is_finite() || tx.amount < 0.0) {
+            return Err(BmsError::invalid_block(height, "transaction amount is negative or not finite"));
+        }
+
+        if current_block.transactions.iter().any(|tx| !tx.fee.is_finite() || tx.fee < 0.0 || (tx.is_coinbase() && tx.fee != 0.0)) {
+            return Err(BmsError::invalid_block(height, "transaction fee is negative, not finite or on a coinbase"));
+        }
+
+        if let Some((treasury_address, _)) = &self.config.treasury {
+            self.validate_treasury_split(current_block, treasury_address)?;
+        }
+
+        self.validate_transaction_order(current_block)?;
+
+        if self.config.validation_level == ValidationLevel::Structural {
+            return Ok(());
+        }
+
+        if current_block.transactions.iter().any(|tx| !tx.is_coinbase() && !tx.verify()) {
+            return Err(BmsError::invalid_block(height, "transaction is not signed by its sender"));
+        }
+
+        let chain_id = self.chain_id();
+        if current_block.transactions.iter().any(|tx| !tx.is_coinbase() && tx.chain_tag != chain_id) {
+            return Err(BmsError::invalid_block(height, "transaction is tagged for a different chain"));
+        }
+
+        if !current_block.transactions.iter().all(|tx| self.memo_fits(tx)) {
+            return Err(BmsError::invalid_block(height, "memo exceeds the maximum length"));
+        }
+
+        if current_block.transactions.iter().any(|tx| tx.is_expired_at(current_block.timestamp)) {
+            return Err(BmsError::invalid_block(height, "transaction was mined after it expired"));
+        }
+
+        self.validate_spends(current_block, ledger)
+    }
+
+    /// Replays the block's transactions against `ledger`, the state at its
+    /// parent: none may already be on the chain, and each sender's must
+    /// follow on from its nonce and stay within what it can spend, as
+    /// checked by `BlockSpends`.
+    fn validate_spends(&self, block: &Block, ledger: &Ledger) -> Result<(), BmsError> {
+        let mut transactions: Vec<&Transaction> = block.transactions.iter().filter(|tx| !tx.is_coinbase()).collect();
+        transactions.sort_by_key(|tx| tx.nonce);
+        let mut spends = BlockSpends::new(ledger, block.index);
+        for tx in transactions {
+            if ledger.mined.contains(&tx.id()) {
+                return Err(BmsError::invalid_block(block.index, "transaction is already on the chain"));
+            }
+            spends.admit(tx).map_err(|reason| BmsError::invalid_block(block.index, reason))?;
+        }
+        Ok(())
+    }
+
+    /// Every mined block carries exactly one reward coinbase, as its first
+    /// transaction, numbered with the block height and paying the miner's
+    /// share of the scheduled subsidy plus the block's fees, then the
+    /// treasury payout when a treasury is configured. Any other coinbase
+    /// would mint coins out of thin air.
+    fn validate_coinbase(&self, block: &Block) -> Result<(), BmsError> {
+        let coinbase_count = block.transactions.iter().filter(|tx| tx.is_coinbase()).count();
+        if coinbase_count != self.coinbase_count() {
+            return Err(BmsError::invalid_block(block.index, "unexpected number of coinbase transactions"));
+        }
+        let reward_tx = match block.transactions.first() {
+            Some(tx) if tx.is_coinbase() && tx.nonce == block.index as u64 => tx,
+            _ => return Err(BmsError::invalid_block(block.index, "first transaction is not the block's coinbase")),
+        };
+        let (_, miner_share) = self.split_subsidy(self.reward_at_height(block.index));
+        let fees = total_fees(&block.transactions[self.coinbase_count()..]);
+        if reward_tx.amount != miner_share + fees {
+            return Err(BmsError::invalid_block(block.index, "coinbase does not pay the scheduled reward plus fees"));
+        }
+        Ok(())
+    }
+
+    /// The treasury payout directly follows the miner's reward and pays
+    /// exactly the configured share.
+    fn validate_treasury_split(&self, block: &Block, treasury_address: &str) -> Result<(), BmsError> {
+        let (treasury_share, _) = self.split_subsidy(self.reward_at_height(block.index));
+        match block.transactions.get(1) {
+            Some(tx) if tx.is_coinbase() && tx.nonce == block.index as u64 && tx.to == treasury_address && tx.amount == treasury_share => Ok(()),
+            _ => Err(BmsError::invalid_block(block.index, "treasury share is missing or incorrect")),
+        }
+    }
+
+    /// After the coinbase transactions, the rest of the block must be in
+    /// strict block order, as `mine_block` builds it.
+    fn validate_transaction_order(&self, block: &Block) -> Result<(), BmsError> {
+        let keys: Vec<_> = block.transactions.iter().skip(self.coinbase_count()).map(|tx| (tx.fee_rate(), tx.id())).collect();
+        if keys.windows(2).any(|pair| block_order(&pair[0], &pair[1]) != Ordering::Less) {
+            return Err(BmsError::invalid_block(block.index, "transactions are not in canonical order"));
+        }
+        Ok(())
+    }
+
+    /// Coinbase transactions in every mined block: the reward, plus the
+    /// treasury payout when configured.
+    fn coinbase_count(&self) -> usize {
+        if self.config.treasury.is_some() { 2 } else { 1 }
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.chain
+    }
+
+    /// Transactions in blocks stamped at or after `timestamp`, paired with
+    /// the height of their block. Block timestamps never decrease along a
+    /// valid chain, so the first matching block is found by binary search.
+    pub fn transactions_since(&self, timestamp: i64) -> Vec<(u32, &Transaction)> {
+        let start = self.chain.partition_point(|block| block.timestamp < timestamp);
+        self.chain[start..]
+            .iter()
+            .flat_map(|block| block.transactions.iter().map(move |tx| (block.index, tx)))
+            .collect()
+    }
+
+    /// Every mined transaction sending to or from `address`, with the height
+    /// of its block, in chain order. Uses the address index when
+    /// `index_addresses` is on and scans the whole chain otherwise.
+    pub fn transactions_for(&self, address: &str) -> Vec<(u32, &Transaction)> {
+        let touches = |tx: &Transaction| tx.from == address || tx.to == address;
+        if !self.config.index_addresses {
+            return self.filter_transactions(touches);
+        }
+        self.address_index
+            .get(address)
+            .into_iter()
+            .flatten()
+            .map(|&height| &self.chain[height as usize])
+            .flat_map(|block| block.transactions.iter().filter(move |tx| touches(tx)).map(move |tx| (block.index, tx)))
+            .collect()
+    }
+
+    fn index_block(&mut self, block: &Block) {
+        if !self.config.index_addresses {
+            return;
+        }
+        for tx in &block.transactions {
+            for address in [&tx.from, &tx.to] {
+                let heights = self.address_index.entry(address.clone()).or_default();
+                if heights.last() != Some(&block.index) {
+                    heights.push(block.index);
+                }
+            }
+        }
+    }
+
+    fn rebuild_address_index(&mut self) {
+        self.address_index.clear();
+        let chain = std::mem::take(&mut self.chain);
+        for block in &chain {
+            self.index_block(block);
+        }
+        self.chain = chain;
+    }
+
+    /// Every mined transaction matching `predicate`, with the height of its
+    /// block, in chain order.
+    pub fn filter_transactions(&self, predicate: impl Fn(&Transaction) -> bool) -> Vec<(u32, &Transaction)> {
+        self.chain
+            .iter()
+            .flat_map(|block| block.transactions.iter().map(move |tx| (block.index, tx)))
+            .filter(|(_, tx)| predicate(tx))
+            .collect()
+    }
+
+    /// Removes the tip block, reverting its effects, and puts its
+    /// non-coinbase transactions back at the front of the mempool. The
+    /// genesis block cannot be undone.
+    pub fn undo_last_block(&mut self) -> Option<Block> {
+        if self.chain.len() <= 1 {
+            return None;
+        }
+        let block = self.chain.pop()?;
+        let returned = block.transactions.iter().filter(|tx| !tx.is_coinbase()).cloned();
+        self.pending_transactions.splice(0..0, returned);
+        self.rebuild_state();
+        Some(block)
+    }
+
+    /// Validates `block` as the successor of the current tip and appends it,
+    /// dropping the pending transactions it includes. The rest of the
+    /// mempool is re-checked against the new state, dropping what it no
+    /// longer admits, such as a conflicting spend from the same sender.
+    pub fn try_append_block(&mut self, block: Block) -> Result<(), BmsError> {
+        self.validate_block(&block, &self.chain, &self.ledger)?;
+        let included: HashSet<TxId> = block.transactions.iter().map(Transaction::id).collect();
+        self.pending_transactions.retain(|tx| !included.contains(&tx.id()));
+        self.push_block(block);
+        self.reconcile_mempool(Vec::new());
+        Ok(())
+    }
+
+    /// Parses a JSON-encoded block and appends it with `try_append_block`.
+    /// Malformed input yields `BmsError::MalformedBlock`; a well-formed but
+    /// invalid block yields the validation error.
+    pub fn verify_and_import_block_json(&mut self, json: &str) -> Result<(), BmsError> {
+        let block: Block = serde_json::from_str(json)?;
+        self.try_append_block(block)
+    }
+
+    /// Writes the chain, mempool, wallets and their signing keys to `path`
+    /// in the format its extension selects (see `SaveFormat::for_path`).
+    pub fn save_to_file(&self, path: &Path) -> io::Result<()> {
+        self.save_to_file_as(path, SaveFormat::for_path(path))
+    }
+
+    /// Writes the chain, mempool, wallets and their signing keys to `path`
+    /// in `format`. The file is written beside `path` first and then renamed
+    /// over it, so an interrupted save never leaves a truncated file. The
+    /// signing keys are written in the clear, so keep the file private or
+    /// use `save_to_file_encrypted`.
+    pub fn save_to_file_as(&self, path: &Path, format: SaveFormat) -> io::Result<()> {
+        write_replacing(path, &self.encode(format)?)
+    }
+
+    /// Writes the chain like `save_to_file`, but encrypted with a key
+    /// derived from `passphrase` (Argon2id, fresh random salt) and sealed
+    /// with ChaCha20-Poly1305, signing keys included. Read it back with
+    /// `load_from_file_encrypted`.
+    pub fn save_to_file_encrypted(&self, path: &Path, passphrase: &str) -> io::Result<()> {
+        let mut salt = [0u8; SALT_BYTES];
+        let mut nonce = [0u8; NONCE_BYTES];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        let plaintext = self.encode(SaveFormat::Cbor)?;
+        let sealed = file_cipher(passphrase, &salt)?
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &plaintext, aad: ENCRYPTED_MAGIC })
+            .map_err(|_| io::Error::other("could not encrypt the chain"))?;
+        let mut bytes = [ENCRYPTED_MAGIC, &salt, &nonce].concat();
+        bytes.extend(sealed);
+        write_replacing(path, &bytes)
+    }
+
+    /// Encodes what `save_to_file_as` writes.
+    fn encode(&self, format: SaveFormat) -> io::Result<Vec<u8>> {
+        let saved = SavedChain {
+            version: SAVE_FORMAT_VERSION,
+            config: self.config.clone(),
+            chain: self.chain.clone(),
+            pending_transactions: self.pending_transactions.clone(),
+            wallets: self.ledger.wallets.clone(),
+            keys: self.keys.iter().map(|(address, key)| (address.clone(), key.to_bytes())).collect(),
+            current_mining_reward: self.current_mining_reward(),
+        };
+        Ok(match format {
+            SaveFormat::Json => serde_json::to_vec_pretty(&saved)?,
+            SaveFormat::Cbor => {
+                let mut bytes = CBOR_MAGIC.to_vec();
+                ciborium::ser::into_writer(&saved, &mut bytes).map_err(|err| io::Error::other(err.to_string()))?;
+                bytes
+            }
+        })
+    }
+
+    /// Restores a chain written by `save_to_file`, with the system clock.
+    /// JSON and CBOR files are told apart by their contents. Files from
+    /// older builds are migrated to the current format first.
+    /// The chain is validated and its state replayed; a file that does not
+    /// parse, is of an unsupported format version, holds a configuration
+    /// `BlockchainBuilder` would refuse, an invalid chain,
+    /// keys that do not own their addresses, or balances that disagree with
+    /// the replay fails with `InvalidData`, as does an encrypted file. Pending
+    /// transactions the restored state no longer admits are dropped.
+    pub fn load_from_file(path: &Path) -> io::Result<Blockchain> {
+        let bytes = fs::read(path)?;
+        if bytes.starts_with(ENCRYPTED_MAGIC) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "chain file is encrypted; load it with its passphrase"));
+        }
+        Blockchain::decode(&bytes)
+    }
+
+    /// Restores a chain written by `save_to_file_encrypted`, checked as
+    /// `load_from_file` checks it. A wrong passphrase, or a file that is
+    /// not encrypted or has been tampered with, fails with `InvalidData`.
+    pub fn load_from_file_encrypted(path: &Path, passphrase: &str) -> io::Result<Blockchain> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let bytes = fs::read(path)?;
+        let sealed = bytes.strip_prefix(ENCRYPTED_MAGIC).ok_or_else(|| invalid("chain file is not encrypted"))?;
+        if sealed.len() < SALT_BYTES + NONCE_BYTES {
+            return Err(invalid("encrypted chain file is truncated"));
+        }
+        let (salt, sealed) = sealed.split_at(SALT_BYTES);
+        let (nonce, ciphertext) = sealed.split_at(NONCE_BYTES);
+        let plaintext = file_cipher(passphrase, salt)?
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: ENCRYPTED_MAGIC })
+            .map_err(|_| invalid("wrong passphrase, or the encrypted chain file is corrupt"))?;
+        Blockchain::decode(&plaintext)
+    }
+
+    /// Parses and checks what `encode` wrote, or an older build did.
+    fn decode(bytes: &[u8]) -> io::Result<Blockchain> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        // The version is read before the rest, so a file in a format this
+        // build does not know fails clearly instead of on some field.
+        let mut saved: serde_json::Value = match bytes.strip_prefix(CBOR_MAGIC) {
+            Some(cbor) => ciborium::de::from_reader(cbor).map_err(|err| invalid(&format!("malformed CBOR chain file: {}", err)))?,
+            None => serde_json::from_slice(bytes)?,
+        };
+        let version = match saved.get("version") {
+            None => 0,
+            Some(version) => version.as_u64().ok_or_else(|| invalid("chain file version is not a number"))?,
+        };
+        match version {
+            0 => migrate_saved_v0(&mut saved),
+            version if version == u64::from(SAVE_FORMAT_VERSION) => {}
+            _ => {
+                return Err(invalid(&format!(
+                    "unsupported chain file version {}; this build reads versions 0 to {}",
+                    version, SAVE_FORMAT_VERSION
+                )))
+            }
+        }
+        let saved: SavedChain = serde_json::from_value(saved)?;
+        if saved.chain.is_empty() {
+            return Err(invalid("saved chain has no genesis block"));
+        }
+        saved.config.check().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let mut blockchain = Blockchain::without_blocks(saved.config, Clock::System, StdRng::from_entropy());
+        blockchain.chain = saved.chain;
+        blockchain.validate().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        for (address, key) in saved.keys {
+            let signing_key = SigningKey::from_bytes(&key);
+            if address_of(signing_key.verifying_key().as_bytes()) != address {
+                return Err(invalid("saved signing key does not own its address"));
+            }
+            blockchain.keys.insert(address, signing_key);
+        }
+        blockchain.ledger.wallets = saved.wallets.keys().map(|address| (address.clone(), 0.0)).collect();
+        blockchain.rebuild_state();
+        if saved.wallets.iter().any(|(address, balance)| blockchain.get_balance(address) != *balance) {
+            return Err(invalid("saved balances do not match the chain"));
+        }
+        if saved.current_mining_reward != blockchain.current_mining_reward() {
+            return Err(invalid("saved mining reward does not match the chain"));
+        }
+
+        blockchain.pending_transactions = saved.pending_transactions;
+        blockchain.reconcile_mempool(Vec::new());
+        let height = blockchain.height();
+        blockchain.pending_since = blockchain.pending_transactions.iter().map(|tx| (tx.id(), height)).collect();
+        Ok(blockchain)
+    }
+
+    /// Adopts `new_chain` if it is valid, shares our genesis block and is
+    /// longer than the current chain. Balances and nonces are rebuilt from
+    /// the new chain and pending transactions it already contains are dropped.
+    /// Transactions from the discarded blocks that the new chain does not
+    /// include go back to the front of the mempool, and the whole mempool is
+    /// re-checked against the new state, dropping what it no longer admits.
+    /// With `finality_depth` set, a chain that diverges from ours at a
+    /// finalized block is refused.
+    pub fn replace_chain(&mut self, new_chain: Vec<Block>) -> Result<(), BmsError> {
+        if new_chain.first().map(|genesis| &genesis.hash) != Some(&self.chain[0].hash) {
+            return Err(BmsError::GenesisMismatch);
+        }
+        if new_chain.len() <= self.chain.len() {
+            return Err(BmsError::ChainNotLonger);
+        }
+        let fork = self.chain.iter().zip(&new_chain).take_while(|(ours, theirs)| ours.hash == theirs.hash).count();
+        if let Some(depth) = self.config.finality_depth {
+            let first_replaced = fork as u32;
+            if first_replaced <= self.height() && self.height() - first_replaced >= depth {
+                return Err(BmsError::FinalizedBlock { height: first_replaced });
+            }
+        }
+        self.validate_chain(&new_chain)?;
+
+        let discarded: Vec<Transaction> = self.chain[fork..]
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .filter(|tx| !tx.is_coinbase())
+            .cloned()
+            .collect();
+        let depth = (self.chain.len() - fork) as u32;
+        let old_tip = std::mem::replace(&mut self.chain, new_chain).pop().map(|block| block.hash).unwrap_or_default();
+        if depth > 0 {
+            self.reorgs.count += 1;
+            self.reorgs.max_depth = self.reorgs.max_depth.max(depth);
+            let new_tip = &self.latest_block().hash;
+            self.log(LogEvent::Reorg { fork_height: fork as u32 - 1, depth, old_tip: &old_tip, new_tip });
+        }
+        self.rebuild_state();
+        self.reconcile_mempool(discarded);
+        Ok(())
+    }
+
+    pub fn reorg_stats(&self) -> ReorgStats {
+        self.reorgs
+    }
+
+    /// Rebuilds the mempool after a reorg: transactions from discarded
+    /// blocks come first, followed by what was already pending, then all of
+    /// them are put in nonce order. Each is re-checked against the new state
+    /// with the ones before it in place, and dropped if it is mined, a
+    /// duplicate or no longer admissible, such as the losing side of a
+    /// double spend or a transaction whose nonce is no longer its sender's
+    /// next one. Survivors keep their nonces and IDs. Returns the
+    /// transactions dropped as no longer admissible.
+    fn reconcile_mempool(&mut self, discarded: Vec<Transaction>) -> Vec<Transaction> {
+        let mut candidates: Vec<Transaction> = discarded.into_iter().chain(std::mem::take(&mut self.pending_transactions)).collect();
+        candidates.sort_by_key(|tx| tx.nonce);
+        let mut seen = HashSet::new();
+        let mut dropped = Vec::new();
+        for tx in candidates {
+            let tx_id = tx.id();
+            if !seen.insert(tx_id.clone()) || self.contains_tx(&tx_id) {
+                continue;
+            }
+            if self.check_transaction(&tx).is_ok() && tx.nonce == self.next_nonce(&tx.from) {
+                self.pending_transactions.push(tx);
+            } else {
+                dropped.push(tx);
+            }
+        }
+        let pending: HashSet<TxId> = self.pending_transactions.iter().map(Transaction::id).collect();
+        self.pending_since.retain(|tx_id, _| pending.contains(tx_id));
+        dropped
+    }
+
+    /// Discards all derived state (balances, nonces, stakes, unbonding and
+    /// the transaction ID filter) and replays it from the genesis block,
+    /// leaving the chain untouched. Returns every address whose balance
+    /// changed, with the balance before and after, sorted by address.
+    pub fn replay_state(&mut self) -> Vec<(String, f64, f64)> {
+        let before = self.ledger.wallets.clone();
+        self.rebuild_state();
+        let mut changes: Vec<(String, f64, f64)> = before
+            .keys()
+            .chain(self.ledger.wallets.keys())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|address| (address.clone(), *before.get(address).unwrap_or(&0.0), self.get_balance(address)))
+            .filter(|(_, before, after)| before != after)
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        changes
+    }
+
+    fn rebuild_state(&mut self) {
+        // Known wallets stay listed, even with nothing on chain.
+        let wallets = self.ledger.wallets.keys().map(|address| (address.clone(), 0.0)).collect();
+        self.ledger = Ledger { wallets, ..Ledger::default() };
+        for block in &self.chain {
+            self.ledger.apply_block(block);
+        }
+        self.rebuild_tx_filter();
+        self.rebuild_address_index();
+        self.heights_by_hash = self.chain.iter().map(|block| (block.hash.clone(), block.index)).collect();
+
+        let chain = &self.chain;
+        self.pending_transactions.retain(|pending| {
+            let id = pending.id();
+            !chain.iter().any(|block| block.transactions.iter().any(|tx| tx.id() == id))
+        });
+        self.debug_check_state();
+    }
+
+    pub fn print_chain(&self) {
+        self.print_chain_with(true);
+    }
+
+    /// Like `print_chain`, but block hashes and transaction IDs are shown
+    /// abbreviated unless `full_hashes` is set.
+    pub fn print_chain_with(&self, full_hashes: bool) {
+        let show = |hash: &str| if full_hashes { hash.to_string() } else { abbreviate_hash(hash) };
+        for (i, block) in self.chain.iter().enumerate() {
+            println!("Block #{}", i);
+            println!("Hash: {}", show(&block.hash));
+            println!("Previous Hash: {}", show(&block.previous_hash));
+            println!("Transactions: {}", block.transactions.len());
+            println!("Volume: {} tokens", format_amount(block.volume()));
+            for (j, tx) in block.transactions.iter().enumerate() {
+                println!("  Transaction {}: {} tokens from {} to {} (fee {}, id {})", j+1, format_amount(tx.amount), tx.from, tx.to, format_amount(tx.fee), show(&tx.id()));
+            }
+            if let Some(message) = block.transactions.first().filter(|tx| tx.is_coinbase()).and_then(|tx| tx.memo.as_ref()) {
+                println!("Coinbase message: {}", message);
+            }
+            println!();
+        }
+        match self.validate() {
+            Ok(()) => println!("Blockchain validity: true"),
+            Err(err) => println!("Blockchain validity: false ({})", err),
+        }
+        println!("Current mining reward: {} tokens", format_amount(self.current_mining_reward()));
+    }
+}
+
+/// Writes `bytes` beside `path` and renames the result over it, so an
+/// interrupted save never leaves a truncated file.
+fn write_replacing(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    fs::write(&partial, bytes)?;
+    fs::rename(&partial, path)
+}
+
+/// The cipher for an encrypted chain file, keyed by Argon2id from
+/// `passphrase` and the file's `salt`.
+fn file_cipher(passphrase: &str, salt: &[u8]) -> io::Result<ChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key).map_err(|err| io::Error::other(err.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Upgrades a chain file from before format versions to version 1. Those
+/// files carry no `version` and predate the minimum relay fee, which every
+/// chain of that era effectively had at zero.
+fn migrate_saved_v0(saved: &mut serde_json::Value) {
+    if let Some(config) = saved.get_mut("config").and_then(serde_json::Value::as_object_mut) {
+        config.entry("min_relay_fee").or_insert(serde_json::json!(0.0));
+    }
+    saved["version"] = serde_json::json!(1);
+}
+
+/// Order of transactions within a block, given each one's fee rate and ID:
+/// highest fee rate first, ties broken by ascending ID. Transactions carry
+/// no submission time to break ties by, and the ID alone keeps the order
+/// total, so every node builds the same block body from the same mempool.
+fn block_order(a: &(f64, TxId), b: &(f64, TxId)) -> Ordering {
+    b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1))
+}
+
+/// `transactions` sorted by `block_order`.
+fn into_block_order(transactions: Vec<Transaction>) -> Vec<Transaction> {
+    let mut keyed: Vec<((f64, TxId), Transaction)> = transactions.into_iter().map(|tx| ((tx.fee_rate(), tx.id()), tx)).collect();
+    keyed.sort_by(|a, b| block_order(&a.0, &b.0));
+    keyed.into_iter().map(|(_, tx)| tx).collect()
+}
+
+/// Sum of the fees `transactions` pay, in order, as credited to the miner.
+fn total_fees(transactions: &[Transaction]) -> f64 {
+    transactions.iter().map(|tx| tx.fee).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::verify_merkle_proof;
+    use crate::builder::BlockchainBuilder;
+
+    /// A chain that mines instantly on a manual clock, with one fresh wallet
+    /// per entry of `funds`, allocated that much at genesis when positive.
+    fn funded_chain(funds: &[f64]) -> (Blockchain, Vec<String>) {
+        let mut blockchain = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).build().unwrap();
+        let addresses = funds
+            .iter()
+            .map(|&amount| {
+                let address = blockchain.create_wallet();
+                if amount > 0.0 {
+                    blockchain.fund_genesis(&address, amount).unwrap();
+                }
+                address
+            })
+            .collect();
+        (blockchain, addresses)
+    }
+
+    fn signed(blockchain: &Blockchain, from: &str, to: &str, amount: f64) -> Transaction {
+        let mut transaction = blockchain.new_transaction(from, to, amount).build();
+        assert!(blockchain.sign_with_wallet(&mut transaction));
+        transaction
+    }
+
+    /// `block` re-mined around `transactions`, so only its contents are wrong.
+    fn resealed(blockchain: &Blockchain, block: Block, transactions: Vec<Transaction>) -> Block {
+        blockchain.seal_block(block.index, block.timestamp, transactions, block.previous_hash)
+    }
+
+    #[test]
+    fn genesis_funding_must_be_positive() {
+        let (mut blockchain, wallets) = funded_chain(&[0.0]);
+        for amount in [f64::NAN, f64::INFINITY, -5.0, 0.0] {
+            assert!(matches!(blockchain.fund_genesis(&wallets[0], amount), Err(BmsError::InvalidConfig(_))));
+        }
+        assert!(blockchain.chain[0].transactions.is_empty());
+        assert!(blockchain.is_chain_valid());
+    }
+
+    #[test]
+    fn transfer_to_oneself_is_rejected() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0]);
+        let self_transfer = signed(&blockchain, &wallets[0], &wallets[0], 10.0);
+        assert_eq!(blockchain.add_transaction(self_transfer), Err(TxRejectReason::SelfTransfer));
+        assert!(blockchain.pending_transactions.is_empty());
+    }
+
+    #[test]
+    fn failed_batch_leaves_the_mempool_as_it_was() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0]);
+        blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], 10.0)).unwrap();
+        let pending_before = blockchain.pending_transactions().to_vec();
+        let since_before = blockchain.pending_since.clone();
+
+        let first = signed(&blockchain, &wallets[0], &wallets[1], 50.0);
+        let mut second = blockchain.new_transaction(&wallets[0], &wallets[1], 50.0).nonce(first.nonce + 1).build();
+        blockchain.sign_with_wallet(&mut second);
+        let result = blockchain.add_transactions_atomic(vec![first, second]);
+        assert_eq!(result, Err(TxRejectReason::InsufficientBalance));
+        let pending_ids = |transactions: &[Transaction]| transactions.iter().map(Transaction::id).collect::<Vec<_>>();
+        assert_eq!(pending_ids(blockchain.pending_transactions()), pending_ids(&pending_before));
+        assert_eq!(blockchain.pending_since, since_before);
+    }
+
+    #[test]
+    fn colliding_wallet_key_does_not_reset_a_balance() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0]);
+        // Rewind the generator so the next key drawn is the one already owned.
+        blockchain.rng = StdRng::seed_from_u64(1);
+        let replayed = SigningKey::generate(&mut StdRng::seed_from_u64(1));
+        assert_eq!(address_of(replayed.verifying_key().as_bytes()), wallets[0]);
+        let second = blockchain.create_wallet();
+        assert_ne!(second, wallets[0]);
+        assert_eq!(blockchain.get_balance(&wallets[0]), 100.0);
+        assert_eq!(blockchain.ledger.wallets.len(), 2);
+    }
+
+    #[test]
+    fn unversioned_chain_file_is_migrated() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0]);
+        blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], 2.0 / 3.0)).unwrap();
+        blockchain.mine_pending_transactions(&wallets[1]).unwrap();
+        let path = std::env::temp_dir().join(format!("bms-v0-{}.json", std::process::id()));
+        blockchain.save_to_file(&path).unwrap();
+        // A version 0 file: no version, and a config from before the
+        // minimum relay fee and retargeting existed.
+        let mut saved: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        saved.as_object_mut().unwrap().remove("version");
+        let config = saved["config"].as_object_mut().unwrap();
+        config.remove("min_relay_fee");
+        config.remove("retarget");
+        fs::write(&path, saved.to_string()).unwrap();
+
+        let loaded = Blockchain::load_from_file(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.config().min_relay_fee, 0.0);
+        assert_eq!(loaded.latest_block().hash, blockchain.latest_block().hash);
+        assert_eq!(loaded.get_balance(&wallets[1]), blockchain.get_balance(&wallets[1]));
+    }
+
+    #[test]
+    fn block_violating_the_treasury_split_is_rejected() {
+        let mut blockchain = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).treasury("0xtreasury", 0.25).build().unwrap();
+        let miner = blockchain.create_wallet();
+        blockchain.mine_until_height(1, &miner);
+        let block = blockchain.chain[1].clone();
+        assert_eq!(block.transactions[1].amount, 25.0);
+        assert!(blockchain.validate().is_ok());
+
+        let mut transactions = block.transactions.clone();
+        transactions[1].amount = 50.0;
+        blockchain.chain[1] = resealed(&blockchain, block, transactions);
+        let err = blockchain.validate().unwrap_err();
+        assert!(err.to_string().contains("treasury share"), "{}", err);
+    }
+
+    #[test]
+    fn block_with_a_second_coinbase_is_rejected() {
+        let (mut blockchain, wallets) = funded_chain(&[0.0]);
+        blockchain.mine_until_height(1, &wallets[0]);
+        let block = blockchain.chain[1].clone();
+        let mut transactions = block.transactions.clone();
+        transactions.push(Transaction::coinbase(wallets[0].clone(), 100.0, 1));
+        blockchain.chain[1] = resealed(&blockchain, block, transactions);
+        let err = blockchain.validate().unwrap_err();
+        assert!(err.to_string().contains("number of coinbase transactions"), "{}", err);
+    }
+
+    #[test]
+    fn block_out_of_canonical_order_is_rejected() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 100.0, 0.0]);
+        blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[2], 10.0)).unwrap();
+        blockchain.add_transaction(signed(&blockchain, &wallets[1], &wallets[2], 10.0)).unwrap();
+        blockchain.mine_pending_transactions(&wallets[2]).unwrap();
+        assert!(blockchain.validate().is_ok());
+
+        let block = blockchain.chain[1].clone();
+        let mut transactions = block.transactions.clone();
+        transactions.swap(1, 2);
+        blockchain.chain[1] = resealed(&blockchain, block, transactions);
+        let err = blockchain.validate().unwrap_err();
+        assert!(err.to_string().contains("not in canonical order"), "{}", err);
+    }
+
+    #[test]
+    fn same_config_builds_the_same_genesis() {
+        let build = || BlockchainBuilder::new().difficulty(1).genesis_timestamp(1_700_000_000).build().unwrap();
+        let (first, second) = (build(), build());
+        assert_eq!(first.genesis_hash(), second.genesis_hash());
+        assert_eq!(first.chain_id(), second.chain_id());
+    }
+
+    #[test]
+    fn transaction_signed_for_another_chain_is_rejected() {
+        let build = |network_id| {
+            BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).network_id(network_id).build().unwrap()
+        };
+        let (mut chain_a, mut chain_b) = (build("bms-a"), build("bms-b"));
+        let sender = chain_a.create_wallet();
+        let recipient = chain_a.create_wallet();
+        assert_eq!(chain_b.create_wallet(), sender);
+        chain_a.fund_genesis(&sender, 100.0).unwrap();
+        chain_b.fund_genesis(&sender, 100.0).unwrap();
+
+        let transfer = signed(&chain_a, &sender, &recipient, 10.0);
+        assert_eq!(chain_b.add_transaction(transfer.clone()), Err(TxRejectReason::WrongChain));
+        assert!(chain_a.add_transaction(transfer).is_ok());
+    }
+
+    #[test]
+    fn eviction_survives_undoing_below_the_entry_height() {
+        let coinbase_bytes = Transaction::coinbase(String::from("0x0000000000000000"), 100.0, 0).serialized_size();
+        let mut blockchain = BlockchainBuilder::new()
+            .difficulty(1)
+            .clock(Clock::manual(0, 1))
+            .seed(1)
+            .mempool_expiry_blocks(3)
+            .max_block_bytes(BLOCK_HEADER_BYTES + coinbase_bytes)
+            .build()
+            .unwrap();
+        let sender = blockchain.create_wallet();
+        let miner = blockchain.create_wallet();
+        blockchain.fund_genesis(&sender, 100.0).unwrap();
+        blockchain.mine_until_height(5, &miner);
+        blockchain.add_transaction(signed(&blockchain, &sender, &miner, 10.0)).unwrap();
+        blockchain.undo_last_block();
+        blockchain.undo_last_block();
+        blockchain.mine_pending_transactions(&miner).unwrap();
+        assert_eq!(blockchain.pending_transactions().len(), 1);
+    }
+
+    #[test]
+    fn reorg_below_the_finality_depth_is_refused() {
+        let mut blockchain =
+            BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).finality_depth(2).build().unwrap();
+        let (miner, rival) = (blockchain.create_wallet(), blockchain.create_wallet());
+        blockchain.mine_until_height(4, &miner);
+        let fork_after = |height: u32| {
+            let mut fork = blockchain.clone();
+            while fork.height() > height {
+                fork.undo_last_block();
+            }
+            fork.mine_until_height(5, &rival);
+            fork.blocks().to_vec()
+        };
+        let (deep, shallow) = (fork_after(1), fork_after(3));
+
+        assert!(matches!(blockchain.replace_chain(deep), Err(BmsError::FinalizedBlock { height: 2 })));
+        assert_eq!(blockchain.height(), 4);
+        blockchain.replace_chain(shallow).unwrap();
+        assert_eq!(blockchain.height(), 5);
+    }
+
+    /// A transfer from `from` that stops being mineable once the tip's
+    /// timestamp is passed, i.e. in the next block on a manual clock.
+    fn expiring_now(blockchain: &Blockchain, from: &str, to: &str) -> Transaction {
+        let deadline = blockchain.latest_block().timestamp;
+        let mut transaction = blockchain.new_transaction(from, to, 10.0).expires_at(deadline).build();
+        assert!(blockchain.sign_with_wallet(&mut transaction));
+        transaction
+    }
+
+    #[test]
+    fn expired_transaction_is_left_out_of_the_next_block() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0]);
+        let tx_id = blockchain.add_transaction(expiring_now(&blockchain, &wallets[0], &wallets[1])).unwrap();
+        let block = blockchain.mine_pending_transactions(&wallets[1]).unwrap();
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(blockchain.last_evicted().iter().map(Transaction::id).collect::<Vec<_>>(), [tx_id]);
+        assert!(blockchain.pending_transactions().is_empty());
+        assert_eq!(blockchain.get_balance(&wallets[0]), 100.0);
+    }
+
+    #[test]
+    fn block_holding_an_expired_transaction_is_rejected() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0]);
+        let expired = expiring_now(&blockchain, &wallets[0], &wallets[1]);
+        let (block, _) = blockchain.speculative_mine(vec![expired], &wallets[1]);
+        let err = blockchain.try_append_block(block).unwrap_err();
+        assert!(err.to_string().contains("mined after it expired"), "{}", err);
+    }
+
+    #[test]
+    fn block_replaying_a_mined_transfer_is_rejected() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0, 0.0]);
+        let tx_id = blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], 10.0)).unwrap();
+        blockchain.mine_pending_transactions(&wallets[2]).unwrap();
+        let mined = blockchain.latest_block().transactions.iter().find(|tx| tx.id() == tx_id).unwrap().clone();
+
+        let (replay, _) = blockchain.speculative_mine(vec![mined], &wallets[2]);
+        let err = blockchain.try_append_block(replay).unwrap_err();
+        assert!(err.to_string().contains("already on the chain"), "{}", err);
+        assert_eq!(blockchain.get_balance(&wallets[0]), 90.0);
+    }
+
+    #[test]
+    fn block_overspending_a_balance_is_rejected() {
+        let (mut blockchain, wallets) = funded_chain(&[90.0, 0.0, 0.0]);
+        let overspend = signed(&blockchain, &wallets[0], &wallets[1], 1000.0);
+        let (block, _) = blockchain.speculative_mine(vec![overspend], &wallets[2]);
+        let err = blockchain.try_append_block(block).unwrap_err();
+        assert!(err.to_string().contains("more than its sender's balance"), "{}", err);
+        assert_eq!(blockchain.get_balance(&wallets[0]), 90.0);
+    }
+
+    #[test]
+    fn replacement_chain_overspending_a_balance_is_refused() {
+        let (mut blockchain, wallets) = funded_chain(&[90.0, 0.0, 0.0]);
+        let overspend = signed(&blockchain, &wallets[0], &wallets[1], 1000.0);
+        let (block, _) = blockchain.speculative_mine(vec![overspend], &wallets[2]);
+        let mut peer_chain = blockchain.blocks().to_vec();
+        peer_chain.push(block);
+        assert!(blockchain.replace_chain(peer_chain).is_err());
+        assert_eq!(blockchain.height(), 0);
+    }
+
+    #[test]
+    fn mined_blocks_pass_validation() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0, 0.0]);
+        for amount in [10.0, 20.0, 30.0] {
+            blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], amount)).unwrap();
+        }
+        blockchain.mine_pending_transactions(&wallets[2]).unwrap();
+        assert_eq!(blockchain.latest_block().transactions.len(), 4);
+        assert!(blockchain.is_chain_valid());
+    }
+
+    #[test]
+    fn relay_batches_are_capped_per_call() {
+        let mut blockchain = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).max_relay_batch(2).build().unwrap();
+        let (sender, recipient) = (blockchain.create_wallet(), blockchain.create_wallet());
+        blockchain.fund_genesis(&sender, 100.0).unwrap();
+        let relayed: Vec<Transaction> = (0..5)
+            .map(|nonce| {
+                let mut transaction = blockchain.new_transaction(&sender, &recipient, 1.0).nonce(nonce).build();
+                blockchain.sign_with_wallet(&mut transaction);
+                transaction
+            })
+            .collect();
+        let merge = blockchain.merge_mempools(&relayed);
+        assert_eq!(merge, MempoolMerge { added: 2, duplicates: 0, rejected: 0, throttled: 3 });
+        // Relaying again admits the next batch.
+        let merge = blockchain.merge_mempools(&relayed);
+        assert_eq!(merge, MempoolMerge { added: 2, duplicates: 2, rejected: 0, throttled: 1 });
+        assert_eq!(blockchain.pending_transactions().len(), 4);
+    }
+
+    #[test]
+    fn filter_transactions_reports_the_heights_of_matches() {
+        let (mut blockchain, wallets) = funded_chain(&[5_000.0, 0.0]);
+        for amount in [500.0, 1_500.0, 2_000.0] {
+            blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], amount)).unwrap();
+            blockchain.mine_pending_transactions(&wallets[1]).unwrap();
+        }
+        let large: Vec<(u32, f64)> =
+            blockchain.filter_transactions(|tx| tx.amount > 1_000.0).into_iter().map(|(height, tx)| (height, tx.amount)).collect();
+        assert_eq!(large, [(0, 5_000.0), (2, 1_500.0), (3, 2_000.0)]);
+        let to_recipient = blockchain.filter_transactions(|tx| tx.to == wallets[1] && !tx.is_coinbase());
+        assert_eq!(to_recipient.iter().map(|(height, _)| *height).collect::<Vec<_>>(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn transfers_below_the_dust_threshold_are_rejected() {
+        let mut blockchain =
+            BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).dust_threshold(1.0).build().unwrap();
+        let (sender, recipient) = (blockchain.create_wallet(), blockchain.create_wallet());
+        blockchain.fund_genesis(&sender, 100.0).unwrap();
+        let dust = signed(&blockchain, &sender, &recipient, 0.99);
+        assert_eq!(blockchain.add_transaction(dust), Err(TxRejectReason::Dust { threshold: 1.0 }));
+        assert!(blockchain.add_transaction(signed(&blockchain, &sender, &recipient, 1.0)).is_ok());
+    }
+
+    #[test]
+    fn reorg_drops_the_losing_side_of_a_double_spend() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 50.0, 0.0, 0.0, 0.0]);
+        let [alice, dave, bob, carol, miner] = &wallets[..] else { unreachable!() };
+        let mut branch = blockchain.clone();
+
+        let to_bob = blockchain.add_transaction(signed(&blockchain, alice, bob, 80.0)).unwrap();
+        let from_dave = blockchain.add_transaction(signed(&blockchain, dave, bob, 20.0)).unwrap();
+        blockchain.mine_pending_transactions(miner).unwrap();
+
+        branch.add_transaction(signed(&branch, alice, carol, 80.0)).unwrap();
+        branch.mine_pending_transactions(miner).unwrap();
+        branch.mine_until_height(2, miner);
+        blockchain.replace_chain(branch.blocks().to_vec()).unwrap();
+
+        assert_eq!(blockchain.get_balance(carol), 80.0);
+        assert_eq!(blockchain.get_balance(bob), 0.0);
+        assert_eq!(blockchain.confirmations(&to_bob), None);
+        let pending: Vec<TxId> = blockchain.pending_transactions().iter().map(Transaction::id).collect();
+        assert_eq!(pending, [from_dave]);
+    }
+
+    #[test]
+    fn appended_block_drops_a_conflicting_pending_spend() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0, 0.0, 0.0]);
+        let [alice, bob, carol, miner] = &wallets[..] else { unreachable!() };
+        let mut peer = blockchain.clone();
+        blockchain.add_transaction(signed(&blockchain, alice, bob, 80.0)).unwrap();
+
+        peer.add_transaction(signed(&peer, alice, carol, 80.0)).unwrap();
+        peer.mine_pending_transactions(miner).unwrap();
+        blockchain.try_append_block(peer.latest_block().clone()).unwrap();
+        assert_eq!(blockchain.get_balance(carol), 80.0);
+        assert!(blockchain.pending_transactions().is_empty());
+    }
+
+    #[test]
+    fn blocks_validate_only_under_their_own_pow_version() {
+        let mut blockchain = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).pow_version(3, 1).build().unwrap();
+        let miner = blockchain.create_wallet();
+        blockchain.mine_until_height(5, &miner);
+        let versions: Vec<u32> = blockchain.blocks().iter().map(|block| block.pow_version).collect();
+        assert_eq!(versions, [0, 0, 0, 1, 1, 1]);
+        assert!(blockchain.is_chain_valid());
+
+        // Each block re-mined under the other version, so its hash and
+        // proof of work are sound but it crosses the activation height.
+        for height in [2, 4] {
+            let block = blockchain.blocks()[height].clone();
+            let wrong_version = 1 - block.pow_version;
+            let restamped = Block::with_pow_version(
+                block.index,
+                block.timestamp,
+                block.transactions,
+                block.previous_hash,
+                blockchain.config.difficulty,
+                wrong_version,
+            );
+            let mut chain = blockchain.blocks()[..height].to_vec();
+            chain.push(restamped);
+            let err = blockchain.validate_chain(&chain).unwrap_err();
+            assert!(err.to_string().contains("wrong proof-of-work version"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn credits_count_once_buried_deep_enough_and_debits_at_once() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0, 0.0]);
+        blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], 10.0)).unwrap();
+        blockchain.mine_pending_transactions(&wallets[2]).unwrap();
+
+        assert_eq!(blockchain.confirmed_balance(&wallets[1], 1), 10.0);
+        assert_eq!(blockchain.confirmed_balance(&wallets[1], 2), 0.0);
+        assert_eq!(blockchain.confirmed_balance(&wallets[0], 2), 90.0);
+
+        blockchain.mine_until_height(2, &wallets[2]);
+        assert_eq!(blockchain.confirmed_balance(&wallets[1], 2), 10.0);
+        assert_eq!(blockchain.confirmed_balance(&wallets[1], 3), 0.0);
+    }
+
+    #[test]
+    fn address_index_agrees_with_a_full_scan() {
+        let play = |index_addresses| {
+            let mut blockchain = BlockchainBuilder::new()
+                .difficulty(1)
+                .clock(Clock::manual(0, 1))
+                .seed(1)
+                .index_addresses(index_addresses)
+                .build()
+                .unwrap();
+            let wallets: Vec<String> = (0..3).map(|_| blockchain.create_wallet()).collect();
+            blockchain.fund_genesis(&wallets[0], 100.0).unwrap();
+            for (to, amount) in [(1, 10.0), (2, 20.0)] {
+                blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[to], amount)).unwrap();
+                blockchain.mine_pending_transactions(&wallets[2]).unwrap();
+            }
+            blockchain.undo_last_block();
+            let mut branch = blockchain.clone();
+            branch.mine_until_height(3, &wallets[1]);
+            blockchain.replace_chain(branch.blocks().to_vec()).unwrap();
+            blockchain.mine_pending_transactions(&wallets[1]).unwrap();
+            (blockchain, wallets)
+        };
+        let ((indexed, wallets), (scanned, _)) = (play(true), play(false));
+        let hashes = |blockchain: &Blockchain| blockchain.blocks().iter().map(|block| block.hash.clone()).collect::<Vec<_>>();
+        assert_eq!(hashes(&indexed), hashes(&scanned));
+        for address in wallets.iter().map(String::as_str).chain(["0"]) {
+            let history = |blockchain: &Blockchain| {
+                blockchain.transactions_for(address).into_iter().map(|(height, tx)| (height, tx.id())).collect::<Vec<_>>()
+            };
+            assert!(!history(&indexed).is_empty());
+            assert_eq!(history(&indexed), history(&scanned), "{}", address);
+        }
+    }
+
+    #[test]
+    fn non_finite_and_non_positive_amounts_are_rejected() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0]);
+        for amount in [f64::INFINITY, f64::NAN, -5.0, -0.0] {
+            let invalid = signed(&blockchain, &wallets[0], &wallets[1], amount);
+            assert_eq!(blockchain.add_transaction(invalid), Err(TxRejectReason::InvalidAmount), "{}", amount);
+        }
+        assert!(blockchain.pending_transactions().is_empty());
+    }
+
+    #[test]
+    fn hash_index_follows_appends_undos_and_reorgs() {
+        let (mut blockchain, wallets) = funded_chain(&[0.0, 0.0]);
+        blockchain.mine_until_height(3, &wallets[0]);
+        let undone = blockchain.undo_last_block().unwrap();
+        assert_eq!(blockchain.height_of_hash(&undone.hash), None);
+
+        let mut branch = blockchain.clone();
+        blockchain.mine_until_height(3, &wallets[0]);
+        let replaced = blockchain.latest_block().hash.clone();
+        assert_eq!(blockchain.height_of_hash(&replaced), Some(3));
+        branch.mine_until_height(4, &wallets[1]);
+        blockchain.replace_chain(branch.blocks().to_vec()).unwrap();
+
+        assert_eq!(blockchain.height_of_hash(&replaced), None);
+        assert!(blockchain.get_block_by_hash(&replaced).is_none());
+        for block in blockchain.blocks() {
+            assert_eq!(blockchain.height_of_hash(&block.hash), Some(block.index));
+        }
+    }
+
+    /// The funded chain's genesis re-mined around `previous_hash` and
+    /// `transactions`.
+    fn forged_genesis(blockchain: &Blockchain, previous_hash: &str, transactions: Vec<Transaction>) -> Block {
+        let genesis = &blockchain.blocks()[0];
+        Block::with_target_bits(0, genesis.timestamp, transactions, previous_hash.to_string(), genesis.target_bits, genesis.pow_version)
+    }
+
+    #[test]
+    fn genesis_with_a_bad_previous_hash_is_rejected() {
+        let (blockchain, _) = funded_chain(&[100.0]);
+        let transactions = blockchain.blocks()[0].transactions.clone();
+        assert!(blockchain.validate_chain(&[forged_genesis(&blockchain, "0", transactions.clone())]).is_ok());
+        let err = blockchain.validate_chain(&[forged_genesis(&blockchain, "1", transactions)]).unwrap_err();
+        assert!(err.to_string().contains("placeholder hash"), "{}", err);
+    }
+
+    #[test]
+    fn genesis_with_an_unexpected_coinbase_is_rejected() {
+        let (blockchain, wallets) = funded_chain(&[100.0, 0.0]);
+        let mut transactions = blockchain.blocks()[0].transactions.clone();
+        transactions.push(Transaction::coinbase(wallets[1].clone(), 1000.0, 1));
+        let err = blockchain.validate_chain(&[forged_genesis(&blockchain, "0", transactions)]).unwrap_err();
+        assert!(err.to_string().contains("genesis allocations"), "{}", err);
+    }
+
+    #[test]
+    fn relayed_transactions_cannot_reuse_a_nonce() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0, 0.0]);
+        let first = signed(&blockchain, &wallets[0], &wallets[1], 10.0);
+        let second = signed(&blockchain, &wallets[0], &wallets[2], 10.0);
+        let merge = blockchain.merge_mempools(&[first, second]);
+        assert_eq!(merge, MempoolMerge { added: 1, duplicates: 0, rejected: 1, throttled: 0 });
+    }
+
+    #[test]
+    fn only_the_sender_can_cancel() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0]);
+        let tx_id = blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], 10.0)).unwrap();
+        let stranger = SigningKey::from_bytes(&[9; 32]);
+        assert_eq!(blockchain.cancel_pending(&tx_id, &stranger), Err(TxRejectReason::InvalidSignature));
+        assert_eq!(blockchain.pending_transactions().len(), 1);
+        assert_eq!(blockchain.cancel_with_wallet(&tx_id), Ok(true));
+        assert!(blockchain.pending_transactions().is_empty());
+    }
+
+    #[test]
+    fn block_skipping_a_nonce_is_rejected() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0, 0.0]);
+        let mut skipped = blockchain.new_transaction(&wallets[0], &wallets[1], 10.0).nonce(1).build();
+        blockchain.sign_with_wallet(&mut skipped);
+        let (block, _) = blockchain.speculative_mine(vec![skipped], &wallets[2]);
+        let err = blockchain.try_append_block(block).unwrap_err();
+        assert!(err.to_string().contains("nonce"), "{}", err);
+    }
+
+    #[test]
+    fn saved_chain_reloads_valid() {
+        let (mut blockchain, wallets) = funded_chain(&[200.0, 0.0]);
+        // Amounts that serde_json's default float parsing reads back off by
+        // one unit in the last place.
+        for amount in [2.0 / 3.0, 7.0 / 9.0, 99.99999999999999] {
+            blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], amount)).unwrap();
+            blockchain.mine_pending_transactions(&wallets[1]).unwrap();
+        }
+        assert_eq!(blockchain.height(), 3);
+
+        let path = std::env::temp_dir().join(format!("bms-round-trip-{}.json", std::process::id()));
+        blockchain.save_to_file(&path).unwrap();
+        let loaded = Blockchain::load_from_file(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert!(loaded.is_chain_valid());
+        assert_eq!(loaded.latest_block().hash, blockchain.latest_block().hash);
+        assert_eq!(loaded.get_balance(&wallets[0]), blockchain.get_balance(&wallets[0]));
+        assert_eq!(loaded.wallet_addresses(), blockchain.wallet_addresses());
+    }
+
+    #[test]
+    fn json_and_cbor_files_load_the_same_chain() {
+        let (mut blockchain, wallets) = funded_chain(&[200.0, 0.0]);
+        for amount in [2.0 / 3.0, 7.0 / 9.0] {
+            blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], amount)).unwrap();
+            blockchain.mine_pending_transactions(&wallets[1]).unwrap();
+        }
+        blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], 0.1)).unwrap();
+
+        let dir = std::env::temp_dir();
+        let json = dir.join(format!("bms-format-{}.json", std::process::id()));
+        let cbor = dir.join(format!("bms-format-{}.cbor", std::process::id()));
+        // A CBOR file is recognised by its header, not its name.
+        let misnamed = dir.join(format!("bms-format-misnamed-{}.json", std::process::id()));
+        blockchain.save_to_file(&json).unwrap();
+        blockchain.save_to_file(&cbor).unwrap();
+        blockchain.save_to_file_as(&misnamed, SaveFormat::Cbor).unwrap();
+        let (json_len, cbor_len) = (fs::metadata(&json).unwrap().len(), fs::metadata(&cbor).unwrap().len());
+        let loaded: Vec<_> = [&json, &cbor, &misnamed].into_iter().map(|path| Blockchain::load_from_file(path)).collect();
+        for path in [&json, &cbor, &misnamed] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert!(cbor_len < json_len, "{} >= {}", cbor_len, json_len);
+        for loaded in loaded {
+            let loaded = loaded.unwrap();
+            assert_eq!(loaded.block_hashes(), blockchain.block_hashes());
+            let pending_ids = |chain: &Blockchain| chain.pending_transactions().iter().map(Transaction::witness_hash).collect::<Vec<_>>();
+            assert_eq!(pending_ids(&loaded), pending_ids(&blockchain));
+            assert_eq!(loaded.wallet_addresses(), blockchain.wallet_addresses());
+            for wallet in &wallets {
+                assert_eq!(loaded.get_balance(wallet), blockchain.get_balance(wallet));
+            }
+        }
+    }
+
+    #[test]
+    fn encrypted_chain_file_needs_its_passphrase() {
+        let (blockchain, wallets) = funded_chain(&[100.0]);
+        let path = std::env::temp_dir().join(format!("bms-sealed-{}.bin", std::process::id()));
+        blockchain.save_to_file_encrypted(&path, "correct horse").unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let key = blockchain.keys[&wallets[0]].to_bytes();
+        assert!(!bytes.windows(key.len()).any(|window| window == key));
+
+        let wrong = Blockchain::load_from_file_encrypted(&path, "battery staple").err().unwrap();
+        let plain = Blockchain::load_from_file(&path).err().unwrap();
+        let loaded = Blockchain::load_from_file_encrypted(&path, "correct horse");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(wrong.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(plain.kind(), io::ErrorKind::InvalidData);
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.latest_block().hash, blockchain.latest_block().hash);
+        assert_eq!(loaded.get_balance(&wallets[0]), 100.0);
+        assert!(loaded.keys.contains_key(&wallets[0]));
+    }
+
+    #[test]
+    fn corrupt_saved_config_is_refused() {
+        let (blockchain, _) = funded_chain(&[100.0]);
+        let path = std::env::temp_dir().join(format!("bms-bad-config-{}.json", std::process::id()));
+        blockchain.save_to_file(&path).unwrap();
+        let clean: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        for (field, value) in [
+            ("halving_interval", serde_json::json!(0)),
+            ("retarget", serde_json::json!({ "interval": 0, "target_block_time": 60, "min_bits": 1, "max_bits": 8 })),
+        ] {
+            let mut saved = clean.clone();
+            saved["config"][field] = value;
+            fs::write(&path, saved.to_string()).unwrap();
+            let err = Blockchain::load_from_file(&path).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", field);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn with_config_refuses_invalid_config() {
+        let config = BlockchainConfig { halving_interval: 0, ..BlockchainConfig::default() };
+        assert!(matches!(Blockchain::with_config(config), Err(BmsError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn pending_transfers_cannot_overspend_together() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 0.0]);
+        assert!(blockchain.add_transaction(signed(&blockchain, &wallets[0], &wallets[1], 60.0)).is_ok());
+        let second = signed(&blockchain, &wallets[0], &wallets[1], 60.0);
+        assert_eq!(blockchain.add_transaction(second), Err(TxRejectReason::InsufficientBalance));
+        assert_eq!(blockchain.pending_transactions().len(), 1);
+    }
+
+    #[test]
+    fn miner_collects_the_reward_plus_every_fee() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 100.0, 0.0, 0.0]);
+        for (sender, fee) in [(0, 1.5), (1, 2.5)] {
+            let mut transfer = blockchain.new_transaction(&wallets[sender], &wallets[2], 10.0).fee(fee).build();
+            blockchain.sign_with_wallet(&mut transfer);
+            blockchain.add_transaction(transfer).unwrap();
+        }
+        let reward = blockchain.current_mining_reward();
+        blockchain.mine_pending_transactions(&wallets[3]).unwrap();
+        assert_eq!(blockchain.get_balance(&wallets[3]), reward + 1.5 + 2.5);
+        assert_eq!(blockchain.get_balance(&wallets[0]), 88.5);
+        assert_eq!(blockchain.get_balance(&wallets[2]), 20.0);
+    }
+
+    #[test]
+    fn block_ignoring_a_retarget_is_rejected() {
+        let mut blockchain = retargeting_chain(1);
+        let miner = blockchain.create_wallet();
+        blockchain.mine_until_height(1, &miner);
+        assert_eq!(blockchain.next_target_bits(), 5);
+
+        let (block, _) = blockchain.speculative_mine(Vec::new(), &miner);
+        let stale =
+            Block::with_target_bits(block.index, block.timestamp, block.transactions, block.previous_hash, 4, block.pow_version);
+        let err = blockchain.try_append_block(stale).unwrap_err();
+        assert!(err.to_string().contains("wrong difficulty target"), "{}", err);
+    }
+
+    fn retargeting_chain(block_time: i64) -> Blockchain {
+        BlockchainBuilder::new()
+            .difficulty_bits(4)
+            .retarget(2, 60, 2, 6)
+            .clock(Clock::manual(0, block_time))
+            .seed(1)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn fast_blocks_ratchet_difficulty_up_to_the_ceiling() {
+        let mut blockchain = retargeting_chain(1);
+        let miner = blockchain.create_wallet();
+        let mut targets = Vec::new();
+        for height in 1..=12 {
+            blockchain.mine_until_height(height, &miner);
+            targets.push(blockchain.latest_block().target_bits);
+        }
+        assert_eq!(targets, [4, 5, 5, 6, 6, 6, 6, 6, 6, 6, 6, 6]);
+        assert!(blockchain.is_chain_valid());
+    }
+
+    #[test]
+    fn fast_window_raises_the_target_at_the_next_boundary() {
+        let mut blockchain = BlockchainBuilder::new()
+            .difficulty_bits(4)
+            .retarget(4, 60, 2, 6)
+            .clock(Clock::manual(0, 1))
+            .seed(1)
+            .build()
+            .unwrap();
+        let miner = blockchain.create_wallet();
+        assert_eq!((blockchain.next_retarget_height(), blockchain.blocks_until_next_retarget()), (Some(4), Some(4)));
+        blockchain.mine_until_height(3, &miner);
+        assert_eq!((blockchain.next_retarget_height(), blockchain.blocks_until_next_retarget()), (Some(4), Some(1)));
+        assert_eq!(blockchain.latest_block().target_bits, 4);
+        // Blocks one second apart against a 60 second target: one bit more.
+        assert_eq!(blockchain.next_target_bits(), 5);
+        blockchain.mine_until_height(4, &miner);
+        assert_eq!(blockchain.latest_block().target_bits, 5);
+        assert_eq!((blockchain.next_retarget_height(), blockchain.blocks_until_next_retarget()), (Some(8), Some(4)));
+
+        let (fixed, _) = funded_chain(&[]);
+        assert_eq!((fixed.next_retarget_height(), fixed.blocks_until_next_retarget()), (None, None));
+    }
+
+    #[test]
+    fn slow_blocks_lower_difficulty_down_to_the_floor() {
+        let mut blockchain = retargeting_chain(1_000);
+        let miner = blockchain.create_wallet();
+        blockchain.mine_until_height(12, &miner);
+        assert_eq!(blockchain.latest_block().target_bits, 2);
+        assert!(blockchain.is_chain_valid());
+    }
+
+    #[test]
+    fn fee_below_the_minimum_relay_fee_is_rejected() {
+        let mut blockchain = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).min_relay_fee(0.5).build().unwrap();
+        let sender = blockchain.create_wallet();
+        let recipient = blockchain.create_wallet();
+        blockchain.fund_genesis(&sender, 100.0).unwrap();
+        for (fee, accepted) in [(0.0, false), (0.49, false), (0.5, true)] {
+            let mut transaction = blockchain.new_transaction(&sender, &recipient, 1.0).fee(fee).build();
+            blockchain.sign_with_wallet(&mut transaction);
+            match blockchain.add_transaction(transaction) {
+                Ok(_) => assert!(accepted, "fee {} was accepted", fee),
+                Err(reason) => {
+                    assert!(!accepted, "fee {} was rejected", fee);
+                    assert_eq!(reason, TxRejectReason::FeeTooLow { min: 0.5 });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn structural_validation_skips_signatures_and_balances() {
+        for (level, accepted) in [(ValidationLevel::Structural, true), (ValidationLevel::Full, false)] {
+            let mut blockchain = BlockchainBuilder::new()
+                .difficulty(1)
+                .clock(Clock::manual(0, 1))
+                .seed(1)
+                .validation_level(level)
+                .build()
+                .unwrap();
+            let sender = blockchain.create_wallet();
+            let recipient = blockchain.create_wallet();
+            let miner = blockchain.create_wallet();
+            blockchain.fund_genesis(&sender, 10.0).unwrap();
+            let mut forged = signed(&blockchain, &sender, &recipient, 5.0);
+            forged.signature[0] ^= 1;
+            let overspend = signed(&blockchain, &recipient, &sender, 50.0);
+
+            let (template, _) = blockchain.speculative_mine(Vec::new(), &miner);
+            let mut transactions = template.transactions.clone();
+            transactions.extend(into_block_order(vec![forged, overspend]));
+            let block = resealed(&blockchain, template, transactions);
+            assert_eq!(blockchain.try_append_block(block).is_ok(), accepted, "{:?}", level);
+            assert!(blockchain.is_chain_valid());
+        }
+    }
+
+    #[test]
+    fn merkle_root_preview_matches_the_mined_block() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 100.0, 0.0]);
+        for (from, amount, fee) in [(0, 1.0, 0.0), (1, 2.0, 0.5), (0, 3.0, 0.25)] {
+            let mut transaction = blockchain.new_transaction(&wallets[from], &wallets[2], amount).fee(fee).build();
+            blockchain.sign_with_wallet(&mut transaction);
+            blockchain.add_transaction(transaction).unwrap();
+        }
+        // Sender 1 cannot afford this one, so the block leaves it out.
+        let mut overspend = blockchain.new_transaction(&wallets[1], &wallets[2], 500.0).build();
+        blockchain.sign_with_wallet(&mut overspend);
+        blockchain.pending_transactions.push(overspend);
+
+        let preview = blockchain.preview_next_merkle_root(&wallets[2]);
+        assert_eq!(blockchain.pending_transactions().len(), 4);
+        blockchain.mine_pending_transactions(&wallets[2]).unwrap();
+        assert_eq!(blockchain.latest_block().transactions.len(), 4);
+        assert_eq!(preview, blockchain.latest_block().merkle_root);
+    }
+
+    #[test]
+    fn mined_transactions_prove_their_inclusion() {
+        let (mut blockchain, wallets) = funded_chain(&[100.0, 100.0, 0.0]);
+        let mut tx_ids = Vec::new();
+        for (from, amount) in [(0, 1.0), (1, 2.0), (0, 3.0)] {
+            tx_ids.push(blockchain.add_transaction(signed(&blockchain, &wallets[from], &wallets[2], amount)).unwrap());
+        }
+        blockchain.mine_pending_transactions(&wallets[2]).unwrap();
+        let pending = blockchain.add_transaction(signed(&blockchain, &wallets[1], &wallets[2], 4.0)).unwrap();
+
+        let block = blockchain.latest_block();
+        for tx_id in &tx_ids {
+            let (height, proof) = blockchain.verify_transaction_in_chain(tx_id).unwrap();
+            assert_eq!(height, block.index);
+            let transaction = block.transactions.iter().find(|tx| &tx.id() == tx_id).unwrap();
+            assert!(verify_merkle_proof(&transaction.witness_hash(), &proof, &block.merkle_root));
+
+            let mut tampered = proof.clone();
+            tampered.siblings[0] = tampered.siblings[0].replace(|digit| digit != '0', "0");
+            assert!(!verify_merkle_proof(&transaction.witness_hash(), &tampered, &block.merkle_root));
+        }
+        assert!(blockchain.verify_transaction_in_chain(&pending).is_none());
+        assert!(blockchain.verify_transaction_in_chain("unknown").is_none());
+    }
+
+    #[test]
+    fn sweep_pays_the_minimum_relay_fee_from_the_balance() {
+        let mut blockchain = BlockchainBuilder::new()
+            .difficulty(1)
+            .clock(Clock::manual(0, 1))
+            .seed(1)
+            .min_relay_fee(0.5)
+            .dust_threshold(1.0)
+            .build()
+            .unwrap();
+        let sender = blockchain.create_wallet();
+        let recipient = blockchain.create_wallet();
+        let poor = blockchain.create_wallet();
+        blockchain.fund_genesis(&sender, 100.0).unwrap();
+        blockchain.fund_genesis(&poor, 1.25).unwrap();
+
+        let tx_id = blockchain.sweep(&sender, &recipient).unwrap();
+        let sweep = blockchain.get_mempool_transaction(&tx_id).unwrap();
+        assert_eq!((sweep.amount, sweep.fee), (99.5, 0.5));
+        assert_eq!(blockchain.spendable_balance(&sender), 0.0);
+        assert_eq!(
+            blockchain.sweep(&poor, &recipient),
+            Err(TxRejectReason::SweepTooSmall { fee: 0.5, threshold: 1.0 })
+        );
+
+        blockchain.mine_pending_transactions(&poor).unwrap();
+        assert_eq!(blockchain.get_balance(&recipient), 99.5);
+        assert_eq!(blockchain.get_balance(&sender), 0.0);
+    }
+
+    #[test]
+    fn block_order_is_by_fee_rate_then_id_whatever_the_submission_order() {
+        let (blockchain, wallets) = funded_chain(&[100.0, 100.0, 100.0, 100.0, 0.0]);
+        let transactions: Vec<Transaction> = [(0, 1.0), (1, 1.0), (2, 1.0), (3, 2.0)]
+            .iter()
+            .map(|&(sender, fee)| {
+                let mut transaction = blockchain.new_transaction(&wallets[sender], &wallets[4], 5.0).fee(fee).build();
+                blockchain.sign_with_wallet(&mut transaction);
+                transaction
+            })
+            .collect();
+
+        let mut bodies = Vec::new();
+        for order in [[0, 1, 2, 3], [3, 2, 1, 0], [2, 0, 3, 1]] {
+            let mut node = blockchain.clone();
+            for i in order {
+                node.add_transaction(transactions[i].clone()).unwrap();
+            }
+            node.mine_pending_transactions(&wallets[4]).unwrap();
+            assert!(node.is_chain_valid());
+            let body: Vec<TxId> = node.latest_block().transactions[1..].iter().map(Transaction::id).collect();
+            bodies.push(body);
+        }
+        assert!(bodies.windows(2).all(|pair| pair[0] == pair[1]));
+        assert_eq!(bodies[0][0], transactions[3].id());
+        let mut equal_fee: Vec<TxId> = transactions[..3].iter().map(Transaction::id).collect();
+        equal_fee.sort();
+        assert_eq!(bodies[0][1..], equal_fee[..]);
+    }
+
+    #[test]
+    fn unknown_save_format_version_is_refused() {
+        let (blockchain, _) = funded_chain(&[100.0]);
+        let path = std::env::temp_dir().join(format!("bms-version-{}.json", std::process::id()));
+        blockchain.save_to_file(&path).unwrap();
+        let mut saved: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        saved["version"] = serde_json::json!(SAVE_FORMAT_VERSION + 1);
+        fs::write(&path, saved.to_string()).unwrap();
+        let loaded = Blockchain::load_from_file(&path);
+        fs::remove_file(&path).unwrap();
+        let err = loaded.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("unsupported chain file version"), "{}", err);
+    }
+}
@@ -0,0 +1,61 @@
+use sha2::{Digest, Sha256};
+
+/// Target false-positive rate at full capacity.
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Fixed-size Bloom filter over strings. A negative answer is definite; a
+/// positive one is wrong with probability of about `FALSE_POSITIVE_RATE`
+/// (1%) as long as no more than `capacity` items have been inserted, and
+/// rises quickly beyond that.
+#[derive(Clone, Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    capacity: usize,
+    len: usize,
+}
+
+impl BloomFilter {
+    /// Sizes the filter for `capacity` items at the target false-positive
+    /// rate: `m = -n ln p / (ln 2)^2` bits and `k = (m / n) ln 2` hashes.
+    pub fn with_capacity(capacity: usize) -> BloomFilter {
+        let capacity = capacity.max(1);
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-(capacity as f64) * FALSE_POSITIVE_RATE.ln() / (ln2 * ln2)).ceil() as u64;
+        let num_hashes = ((num_bits as f64 / capacity as f64) * ln2).round().max(1.0) as u32;
+        BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+            capacity,
+            len: 0,
+        }
+    }
+
+    pub fn insert(&mut self, item: &str) {
+        let positions: Vec<u64> = self.bit_positions(item).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    pub fn probably_contains(&self, item: &str) -> bool {
+        self.bit_positions(item).all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Whether more items have been inserted than the filter was sized for.
+    pub fn is_saturated(&self) -> bool {
+        self.len > self.capacity
+    }
+
+    /// Double hashing: position `i` is `h1 + i * h2`, with `h1` and `h2`
+    /// taken from a SHA-256 digest of the item.
+    fn bit_positions(&self, item: &str) -> impl Iterator<Item = u64> + '_ {
+        let digest = Sha256::digest(item.as_bytes());
+        let h1 = u64::from_le_bytes(digest[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().unwrap()) | 1;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+}
@@ -0,0 +1,219 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::blockchain::Blockchain;
+use crate::clock::Clock;
+use crate::config::{BlockchainConfig, Retarget, RewardRamp, ValidationLevel};
+use crate::error::BmsError;
+use crate::log::LogFormat;
+
+/// Chained setup for a `Blockchain`, validated as a whole by `build`.
+#[derive(Clone, Debug)]
+pub struct BlockchainBuilder {
+    config: BlockchainConfig,
+    clock: Clock,
+    seed: Option<u64>,
+}
+
+impl Default for BlockchainBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockchainBuilder {
+    pub fn new() -> BlockchainBuilder {
+        BlockchainBuilder {
+            config: BlockchainConfig::default(),
+            clock: Clock::System,
+            seed: None,
+        }
+    }
+
+    pub fn difficulty(mut self, difficulty: usize) -> Self {
+        self.config.difficulty = difficulty;
+        self
+    }
+
+    /// Sets the target in leading zero bits instead of hex digits, for
+    /// steps finer than a factor of 16.
+    pub fn difficulty_bits(mut self, bits: usize) -> Self {
+        self.config.difficulty_bits = Some(bits);
+        self
+    }
+
+    pub fn genesis_difficulty(mut self, genesis_difficulty: usize) -> Self {
+        self.config.genesis_difficulty = genesis_difficulty;
+        self
+    }
+
+    pub fn reward(mut self, mining_reward: f64) -> Self {
+        self.config.mining_reward = mining_reward;
+        self
+    }
+
+    pub fn halving_interval(mut self, halving_interval: u32) -> Self {
+        self.config.halving_interval = halving_interval;
+        self
+    }
+
+    pub fn network_id(mut self, network_id: &str) -> Self {
+        self.config.network_id = network_id.to_string();
+        self
+    }
+
+    /// Genesis allocations. They are sorted by address so the genesis block
+    /// does not depend on the iteration order of the source collection.
+    pub fn genesis_alloc(mut self, allocations: impl IntoIterator<Item = (String, f64)>) -> Self {
+        let mut allocations: Vec<_> = allocations.into_iter().collect();
+        allocations.sort_by(|a, b| a.0.cmp(&b.0));
+        self.config.genesis_alloc = allocations;
+        self
+    }
+
+    pub fn genesis_timestamp(mut self, timestamp: i64) -> Self {
+        self.config.genesis_timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn treasury(mut self, address: &str, fraction: f64) -> Self {
+        self.config.treasury = Some((address.to_string(), fraction));
+        self
+    }
+
+    pub fn reward_ramp(mut self, blocks: u32, start_fraction: f64) -> Self {
+        self.config.reward_ramp = Some(RewardRamp { blocks, start_fraction });
+        self
+    }
+
+    pub fn max_block_bytes(mut self, max_block_bytes: usize) -> Self {
+        self.config.max_block_bytes = Some(max_block_bytes);
+        self
+    }
+
+    pub fn max_tx_amount(mut self, max_tx_amount: f64) -> Self {
+        self.config.max_tx_amount = Some(max_tx_amount);
+        self
+    }
+
+    pub fn mempool_expiry_blocks(mut self, blocks: u32) -> Self {
+        self.config.mempool_expiry_blocks = Some(blocks);
+        self
+    }
+
+    pub fn finality_depth(mut self, depth: u32) -> Self {
+        self.config.finality_depth = Some(depth);
+        self
+    }
+
+    pub fn max_memo_bytes(mut self, max_memo_bytes: usize) -> Self {
+        self.config.max_memo_bytes = max_memo_bytes;
+        self
+    }
+
+    pub fn max_mempool_size(mut self, max_mempool_size: usize) -> Self {
+        self.config.max_mempool_size = Some(max_mempool_size);
+        self
+    }
+
+    pub fn max_relay_batch(mut self, max_relay_batch: usize) -> Self {
+        self.config.max_relay_batch = Some(max_relay_batch);
+        self
+    }
+
+    pub fn dust_threshold(mut self, dust_threshold: f64) -> Self {
+        self.config.dust_threshold = dust_threshold;
+        self
+    }
+
+    pub fn min_relay_fee(mut self, min_relay_fee: f64) -> Self {
+        self.config.min_relay_fee = min_relay_fee;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.config.verbose = verbose;
+        self
+    }
+
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.config.log_format = log_format;
+        self
+    }
+
+    pub fn allow_empty_blocks(mut self, allow_empty_blocks: bool) -> Self {
+        self.config.allow_empty_blocks = allow_empty_blocks;
+        self
+    }
+
+    /// Switches to proof-of-work `version` from `activation_height` on.
+    pub fn pow_version(mut self, activation_height: u32, version: u32) -> Self {
+        self.config.pow_versions.push((activation_height, version));
+        self
+    }
+
+    pub fn max_block_coinbase(mut self, max_block_coinbase: f64) -> Self {
+        self.config.max_block_coinbase = Some(max_block_coinbase);
+        self
+    }
+
+    pub fn index_addresses(mut self, index_addresses: bool) -> Self {
+        self.config.index_addresses = index_addresses;
+        self
+    }
+
+    pub fn validation_level(mut self, validation_level: ValidationLevel) -> Self {
+        self.config.validation_level = validation_level;
+        self
+    }
+
+    /// Retargets the difficulty every `interval` blocks toward one block
+    /// per `target_block_time` seconds, keeping the target between
+    /// `min_bits` and `max_bits` leading zero bits.
+    pub fn retarget(mut self, interval: u32, target_block_time: i64, min_bits: usize, max_bits: usize) -> Self {
+        self.config.retarget = Some(Retarget { interval, target_block_time, min_bits, max_bits });
+        self
+    }
+
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Seeds the wallet address RNG for reproducible runs.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn build(self) -> Result<Blockchain, BmsError> {
+        self.config.check()?;
+        let rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Ok(Blockchain::build(self.config, self.clock, rng))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn genesis_without_work_is_valid() {
+        let blockchain = BlockchainBuilder::new().genesis_difficulty(0).build().unwrap();
+        assert_eq!(blockchain.genesis_hash(), blockchain.blocks()[0].calculate_hash());
+        assert!(blockchain.is_chain_valid());
+    }
+
+    #[test]
+    fn retarget_bounds_are_checked() {
+        assert!(BlockchainBuilder::new().retarget(10, 60, 0, 20).build().is_err());
+        assert!(BlockchainBuilder::new().retarget(10, 60, 20, 8).build().is_err());
+        assert!(BlockchainBuilder::new().retarget(10, 60, 8, 300).build().is_err());
+        // The default target of 16 bits lies outside 4..=8.
+        assert!(BlockchainBuilder::new().retarget(10, 60, 4, 8).build().is_err());
+        assert!(BlockchainBuilder::new().difficulty_bits(6).retarget(10, 60, 4, 8).build().is_ok());
+    }
+}
@@ -0,0 +1,43 @@
+use chrono::Utc;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Source of block timestamps for a `Blockchain`.
+#[derive(Clone, Debug)]
+pub enum Clock {
+    /// Wall-clock time from `Utc::now()`.
+    System,
+    /// Starts at `now` and advances by `step` seconds every time it is read,
+    /// so repeated runs produce identical timestamps.
+    Manual { now: i64, step: i64 },
+    /// Like `Manual`, but each read advances by `step` plus a random offset
+    /// in `-jitter..=jitter` seconds (never going backwards), drawn from a
+    /// seeded RNG so runs stay reproducible.
+    Jittered { now: i64, step: i64, jitter: i64, rng: Box<StdRng> },
+}
+
+impl Clock {
+    pub fn manual(start: i64, step: i64) -> Clock {
+        Clock::Manual { now: start, step }
+    }
+
+    pub fn jittered(start: i64, step: i64, jitter: i64, seed: u64) -> Clock {
+        Clock::Jittered { now: start, step, jitter: jitter.abs(), rng: Box::new(StdRng::seed_from_u64(seed)) }
+    }
+
+    pub fn now(&mut self) -> i64 {
+        match self {
+            Clock::System => Utc::now().timestamp(),
+            Clock::Manual { now, step } => {
+                let timestamp = *now;
+                *now += *step;
+                timestamp
+            }
+            Clock::Jittered { now, step, jitter, rng } => {
+                let timestamp = *now;
+                *now += (*step + rng.gen_range(-*jitter..=*jitter)).max(0);
+                timestamp
+            }
+        }
+    }
+}
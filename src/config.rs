@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::BmsError;
+use crate::log::LogFormat;
+use crate::{DEFAULT_NETWORK_ID, DIFFICULTY, GENESIS_DIFFICULTY, HALVING_INTERVAL, MAX_MEMO_BYTES, MINING_REWARD};
+
+/// Slow-start schedule for the block subsidy. Block `h < blocks` earns
+/// `start_fraction + (1 - start_fraction) * h / blocks` of the scheduled
+/// reward; from block `blocks` on the full reward applies.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RewardRamp {
+    pub blocks: u32,
+    pub start_fraction: f64,
+}
+
+/// Difficulty retargeting. Every `interval` blocks the proof-of-work target
+/// moves by one bit, so at most a factor of two, when the last `interval`
+/// blocks came in at under half, or over twice, `target_block_time` seconds
+/// apart on average. It never leaves `min_bits..=max_bits`, so a run of
+/// extreme block times can neither make blocks free nor hang mining.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Retarget {
+    pub interval: u32,
+    pub target_block_time: i64,
+    /// Easiest target, in leading zero bits.
+    pub min_bits: usize,
+    /// Hardest target, in leading zero bits.
+    pub max_bits: usize,
+}
+
+/// How much of each block `Blockchain` checks before accepting it, when
+/// validating the chain, appending a block or adopting a replacement chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValidationLevel {
+    /// Only what the blocks show on their own: indexes, links, timestamps,
+    /// hashes, merkle roots, proof of work and difficulty, block size, the
+    /// coinbase, the treasury split and transaction order. Signatures,
+    /// chain tags, memos, expiry, nonces and balances go unchecked, which
+    /// suits hand-built demo chains and is unsafe for anything else.
+    Structural,
+    /// Every check, including that each transaction is signed by its sender
+    /// and that each sender can afford what it spends.
+    #[default]
+    Full,
+}
+
+/// Chain-wide parameters fixed when a `Blockchain` is created.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockchainConfig {
+    /// Leading zero hex digits a block hash needs.
+    pub difficulty: usize,
+    /// Finer-grained target in leading zero bits (four per hex digit), as
+    /// checked by `meets_difficulty_bits`. Overrides `difficulty` when set.
+    pub difficulty_bits: Option<usize>,
+    /// Leading zero hex digits the genesis block's hash needs. Kept low by
+    /// default so creating a chain is instant whatever `difficulty` is; 0
+    /// means no work at all.
+    pub genesis_difficulty: usize,
+    /// Subsidy of a block before any halving.
+    pub mining_reward: f64,
+    /// Blocks between halvings of the subsidy.
+    pub halving_interval: u32,
+    /// Coins credited by the genesis block, in order.
+    pub genesis_alloc: Vec<(String, f64)>,
+    /// Mixed into `chain_id` so two networks started from the same genesis
+    /// block are still told apart.
+    pub network_id: String,
+    /// Address and fraction (0.0 to 1.0) of every block subsidy paid to a
+    /// treasury instead of the miner.
+    pub treasury: Option<(String, f64)>,
+    /// Reduced rewards for the first blocks so an early miner cannot claim
+    /// a large share of the supply before others join.
+    pub reward_ramp: Option<RewardRamp>,
+    /// Upper bound on `Block::size` for mined blocks; pending transactions
+    /// that do not fit wait for a later block.
+    pub max_block_bytes: Option<usize>,
+    /// Fixed timestamp for the genesis block so independently started nodes
+    /// agree on its hash (and thus on `chain_id`). Uses the clock if unset.
+    pub genesis_timestamp: Option<i64>,
+    /// Largest amount a single transfer may move. Coinbase transactions are
+    /// exempt.
+    pub max_tx_amount: Option<f64>,
+    /// Blocks a transaction may stay pending before it is evicted from the
+    /// mempool, counted from the height at which it entered.
+    pub mempool_expiry_blocks: Option<u32>,
+    /// Blocks this many below the tip are final: a replacement chain that
+    /// differs from ours at or before such a block is refused.
+    pub finality_depth: Option<u32>,
+    /// Longest memo, in bytes, a transaction (including a coinbase message)
+    /// may carry.
+    pub max_memo_bytes: usize,
+    /// Most transactions the mempool holds; further submissions and relayed
+    /// transactions are refused until mining makes room.
+    pub max_mempool_size: Option<usize>,
+    /// Most transactions a single `merge_mempools` call admits, so one
+    /// peer's relay cannot fill the mempool at once.
+    pub max_relay_batch: Option<usize>,
+    /// Smallest amount a transfer may move; 0.0 allows any amount.
+    pub dust_threshold: f64,
+    /// Smallest fee a transaction must pay to be accepted into the mempool,
+    /// submitted or relayed; 0.0 accepts any. A relay policy only: blocks
+    /// holding cheaper transactions are still valid.
+    pub min_relay_fee: f64,
+    /// Print mining progress (each mined block and reward halvings) to
+    /// stdout. Off by default so embedding the library stays quiet.
+    pub verbose: bool,
+    /// Text or JSON lines for the output enabled by `verbose`.
+    pub log_format: LogFormat,
+    /// Whether a block may be mined with nothing but its coinbase. When off,
+    /// mining an empty mempool fails instead.
+    pub allow_empty_blocks: bool,
+    /// `(activation height, version)` pairs: blocks from each activation
+    /// height on are mined and validated under that proof-of-work version.
+    /// Heights before the first activation use version 0.
+    pub pow_versions: Vec<(u32, u32)>,
+    /// Sanity ceiling on the total coinbase value of any mined block, fees
+    /// included, independent of the reward schedule. Blocks above it fail
+    /// validation.
+    pub max_block_coinbase: Option<f64>,
+    /// Keep an address to block heights index so `transactions_for` can skip
+    /// unrelated blocks. Costs memory proportional to the chain.
+    pub index_addresses: bool,
+    /// Adjust the difficulty to block times instead of keeping `difficulty`
+    /// throughout; `difficulty` is then only the starting point.
+    pub retarget: Option<Retarget>,
+    /// How thoroughly blocks are validated. Chain files from before this
+    /// setting validate fully.
+    #[serde(default)]
+    pub validation_level: ValidationLevel,
+}
+
+impl BlockchainConfig {
+    /// Configured proof-of-work target for the block at `height`, in leading
+    /// zero bits. With `retarget` on, only the starting target.
+    pub fn target_bits_at(&self, height: u32) -> usize {
+        if height == 0 {
+            self.genesis_difficulty * 4
+        } else {
+            self.difficulty_bits.unwrap_or(self.difficulty * 4)
+        }
+    }
+
+    /// Proof-of-work version in force for the block at `height`.
+    pub fn pow_version_at(&self, height: u32) -> u32 {
+        self.pow_versions
+            .iter()
+            .filter(|(activation, _)| *activation <= height)
+            .max_by_key(|(activation, _)| *activation)
+            .map_or(0, |(_, version)| *version)
+    }
+
+    /// Checks that the parameters describe a usable chain: the same rules
+    /// whether the configuration comes from `BlockchainBuilder`,
+    /// `Blockchain::with_config` or a saved chain file.
+    pub(crate) fn check(&self) -> Result<(), BmsError> {
+        // Proof of work is the only consensus mode, so every block needs
+        // some work behind it.
+        if self.difficulty == 0 {
+            return Err(BmsError::InvalidConfig(String::from("difficulty must be at least 1")));
+        }
+        if self.difficulty_bits.is_some_and(|bits| bits == 0 || bits > 256) {
+            return Err(BmsError::InvalidConfig(String::from("difficulty bits must be between 1 and 256")));
+        }
+        if self.halving_interval == 0 {
+            return Err(BmsError::InvalidConfig(String::from("halving interval must be at least 1")));
+        }
+        if !self.mining_reward.is_finite() || self.mining_reward < 0.0 {
+            return Err(BmsError::InvalidConfig(String::from("mining reward must be a non-negative number")));
+        }
+        if let Some((_, fraction)) = &self.treasury {
+            if !(0.0..=1.0).contains(fraction) {
+                return Err(BmsError::InvalidConfig(String::from("treasury fraction must be between 0 and 1")));
+            }
+        }
+        if let Some(ramp) = &self.reward_ramp {
+            if !(0.0..=1.0).contains(&ramp.start_fraction) {
+                return Err(BmsError::InvalidConfig(String::from("reward ramp start fraction must be between 0 and 1")));
+            }
+        }
+        if let Some(retarget) = &self.retarget {
+            // Block times are measured between the blocks of one interval.
+            if retarget.interval < 2 {
+                return Err(BmsError::InvalidConfig(String::from("retarget interval must be at least 2")));
+            }
+            if retarget.target_block_time < 1 {
+                return Err(BmsError::InvalidConfig(String::from("target block time must be at least 1 second")));
+            }
+            if retarget.min_bits == 0 || retarget.min_bits > retarget.max_bits || retarget.max_bits > 256 {
+                return Err(BmsError::InvalidConfig(String::from("retarget bounds must satisfy 1 <= min bits <= max bits <= 256")));
+            }
+            if !(retarget.min_bits..=retarget.max_bits).contains(&self.target_bits_at(1)) {
+                return Err(BmsError::InvalidConfig(String::from("starting difficulty must lie within the retarget bounds")));
+            }
+        }
+        if let Some(max) = self.max_tx_amount {
+            if !max.is_finite() || max <= 0.0 {
+                return Err(BmsError::InvalidConfig(String::from("maximum transaction amount must be positive")));
+            }
+        }
+        if let Some(max) = self.max_block_coinbase {
+            if !max.is_finite() || max <= 0.0 {
+                return Err(BmsError::InvalidConfig(String::from("block coinbase cap must be positive")));
+            }
+        }
+        if !self.dust_threshold.is_finite() || self.dust_threshold < 0.0 {
+            return Err(BmsError::InvalidConfig(String::from("dust threshold must be a non-negative number")));
+        }
+        if !self.min_relay_fee.is_finite() || self.min_relay_fee < 0.0 {
+            return Err(BmsError::InvalidConfig(String::from("minimum relay fee must be a non-negative number")));
+        }
+        if self.mempool_expiry_blocks == Some(0) {
+            return Err(BmsError::InvalidConfig(String::from("mempool expiry must be at least 1 block")));
+        }
+        if self.max_relay_batch == Some(0) {
+            return Err(BmsError::InvalidConfig(String::from("relay batch limit must be at least 1 transaction")));
+        }
+        if self.finality_depth == Some(0) {
+            return Err(BmsError::InvalidConfig(String::from("finality depth must be at least 1 block")));
+        }
+        let mut activations: Vec<u32> = self.pow_versions.iter().map(|(height, _)| *height).collect();
+        activations.sort_unstable();
+        if activations.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(BmsError::InvalidConfig(String::from("each height can activate only one proof-of-work version")));
+        }
+        if self.genesis_alloc.iter().any(|(_, amount)| !amount.is_finite() || *amount <= 0.0) {
+            return Err(BmsError::InvalidConfig(String::from("genesis allocations must be positive amounts")));
+        }
+        Ok(())
+    }
+}
+
+impl Default for BlockchainConfig {
+    fn default() -> Self {
+        BlockchainConfig {
+            difficulty: DIFFICULTY,
+            difficulty_bits: None,
+            genesis_difficulty: GENESIS_DIFFICULTY,
+            mining_reward: MINING_REWARD,
+            halving_interval: HALVING_INTERVAL,
+            genesis_alloc: Vec::new(),
+            network_id: String::from(DEFAULT_NETWORK_ID),
+            treasury: None,
+            reward_ramp: None,
+            max_block_bytes: None,
+            genesis_timestamp: None,
+            max_tx_amount: None,
+            mempool_expiry_blocks: None,
+            finality_depth: None,
+            max_memo_bytes: MAX_MEMO_BYTES,
+            max_mempool_size: None,
+            max_relay_batch: None,
+            dust_threshold: 0.0,
+            min_relay_fee: 0.0,
+            verbose: false,
+            log_format: LogFormat::Text,
+            allow_empty_blocks: true,
+            pow_versions: Vec::new(),
+            max_block_coinbase: None,
+            index_addresses: false,
+            retarget: None,
+            validation_level: ValidationLevel::Full,
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::transaction::TxRejectReason;
+
+/// Errors returned by the fallible `Blockchain` operations.
+#[derive(Debug, Error)]
+pub enum BmsError {
+    #[error("block {height} is invalid: {reason}")]
+    InvalidBlock { height: u32, reason: String },
+
+    #[error("replacement chain starts from a different genesis block")]
+    GenesisMismatch,
+
+    #[error("replacement chain is not longer than the current chain")]
+    ChainNotLonger,
+
+    #[error("replacement chain would rewrite finalized block {height}")]
+    FinalizedBlock { height: u32 },
+
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("no pending transactions to mine and empty blocks are disabled")]
+    NothingToMine,
+
+    #[error("genesis allocations are only possible before the first block is mined")]
+    GenesisSealed,
+
+    #[error("transaction rejected: {0}")]
+    TransactionRejected(#[from] TxRejectReason),
+
+    #[error("could not parse block: {0}")]
+    MalformedBlock(#[from] serde_json::Error),
+
+    #[error("storage error: {0}")]
+    Persistence(#[from] io::Error),
+
+    #[error("tracked state for {address} does not match the chain")]
+    StateDesync { address: String },
+
+    #[error("peer is on chain {theirs}, expected {ours}")]
+    ChainIdMismatch { ours: String, theirs: String },
+}
+
+impl BmsError {
+    pub(crate) fn invalid_block(height: u32, reason: &str) -> BmsError {
+        BmsError::InvalidBlock { height, reason: reason.to_string() }
+    }
+}
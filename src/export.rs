@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+
+use crate::blockchain::Blockchain;
+use crate::error::BmsError;
+use crate::format::format_amount;
+use crate::transaction::TransactionKind;
+use crate::UNSTAKE_COOLDOWN_BLOCKS;
+
+const WALLET_CSV_HEADER: &str = "height,timestamp,tx_id,entry,counterparty,amount,balance";
+const WALLET_LIST_HEADER: &str = "address,balance,nonce";
+
+impl Blockchain {
+    /// Writes a statement for `address` to `path` as CSV: one row per mined
+    /// transaction touching it, in chain order, with the signed change to
+    /// its liquid balance (fees included) and the running balance after it.
+    /// Coinbase receipts are entered as `coinbase`, and unstaked coins coming
+    /// out of their cooldown get a `release` row of their own.
+    pub fn export_wallet_csv(&self, address: &str, path: &str) -> Result<(), BmsError> {
+        fs::write(path, self.wallet_csv(address))?;
+        Ok(())
+    }
+
+    fn wallet_csv(&self, address: &str) -> String {
+        let mut csv = String::from(WALLET_CSV_HEADER);
+        csv.push('\n');
+        let mut balance = 0.0;
+        let mut unbonding: Vec<(u32, f64)> = Vec::new();
+        for block in self.blocks() {
+            let (released, still_bonding) =
+                unbonding.into_iter().partition::<Vec<_>, _>(|&(release_height, _)| release_height <= block.index);
+            unbonding = still_bonding;
+            for (_, amount) in released {
+                balance += amount;
+                let _ = writeln!(
+                    csv,
+                    "{},{},,release,,{},{}",
+                    block.index,
+                    block.timestamp,
+                    format_amount(amount),
+                    format_amount(balance)
+                );
+            }
+
+            for tx in block.transactions.iter().filter(|tx| tx.from == address || tx.to == address) {
+                let (entry, counterparty, change) = match tx.kind {
+                    TransactionKind::Transfer if tx.is_coinbase() => ("coinbase", "", tx.amount),
+                    TransactionKind::Transfer if tx.to == address => ("received", tx.from.as_str(), tx.amount),
+                    TransactionKind::Transfer => ("sent", tx.to.as_str(), -tx.liquid_cost()),
+                    TransactionKind::Stake => ("stake", "", -tx.liquid_cost()),
+                    TransactionKind::Unstake => {
+                        unbonding.push((block.index + UNSTAKE_COOLDOWN_BLOCKS, tx.amount));
+                        ("unstake", "", -tx.liquid_cost())
+                    }
+                };
+                balance += change;
+                let _ = writeln!(
+                    csv,
+                    "{},{},{},{},{},{},{}",
+                    block.index,
+                    block.timestamp,
+                    tx.id(),
+                    entry,
+                    counterparty,
+                    format_amount(change),
+                    format_amount(balance)
+                );
+            }
+        }
+        csv
+    }
+
+    /// Writes `addresses` to `path` as CSV with each one's current balance
+    /// and next nonce, so a front end can restore its wallet list later
+    /// with `import_wallet_list`.
+    pub fn export_wallet_list(&self, addresses: &[String], path: &str) -> Result<(), BmsError> {
+        let mut csv = String::from(WALLET_LIST_HEADER);
+        csv.push('\n');
+        for address in addresses {
+            let _ = writeln!(csv, "{},{},{}", address, format_amount(self.get_balance(address)), self.next_nonce(address));
+        }
+        fs::write(path, csv)?;
+        Ok(())
+    }
+
+    /// Reads a wallet list written by `export_wallet_list` and splits its
+    /// addresses, in file order, into those this chain holds the signing
+    /// key for and those it does not, which can no longer be used. The
+    /// balances and nonces in the file are informational and not read back.
+    pub fn import_wallet_list(&self, path: &str) -> Result<(Vec<String>, Vec<String>), BmsError> {
+        let known: HashSet<String> = self.wallet_addresses().into_iter().collect();
+        let addresses = fs::read_to_string(path)?
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split(',').next())
+            .map(str::trim)
+            .filter(|address| !address.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+        Ok(addresses.into_iter().partition(|address| known.contains(address)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::BlockchainBuilder;
+    use crate::clock::Clock;
+
+    #[test]
+    fn wallet_list_round_trips_and_flags_unknown_addresses() {
+        let mut blockchain = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).build().unwrap();
+        let (alice, bob) = (blockchain.create_wallet(), blockchain.create_wallet());
+        blockchain.fund_genesis(&alice, 100.0).unwrap();
+        let mut transfer = blockchain.new_transaction(&alice, &bob, 12.5).build();
+        blockchain.sign_with_wallet(&mut transfer);
+        blockchain.add_transaction(transfer).unwrap();
+        blockchain.mine_pending_transactions(&bob).unwrap();
+
+        let path = std::env::temp_dir().join(format!("bms-wallets-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let gone = String::from("0xgone");
+        blockchain.export_wallet_list(&[bob.clone(), alice.clone(), gone.clone()], path).unwrap();
+        let csv = fs::read_to_string(path).unwrap();
+        let imported = blockchain.import_wallet_list(path);
+        fs::remove_file(path).unwrap();
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "address,balance,nonce");
+        assert_eq!(lines[1], format!("{},112.5,0", bob));
+        assert_eq!(lines[2], format!("{},87.5,1", alice));
+        assert_eq!(lines[3], "0xgone,0,0");
+        assert_eq!(imported.unwrap(), (vec![bob, alice], vec![gone]));
+    }
+}
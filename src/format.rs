@@ -0,0 +1,47 @@
+use crate::AMOUNT_DECIMALS;
+
+/// Renders `amount` with `AMOUNT_DECIMALS` decimal places, trimming
+/// trailing zeros (`100.00000000` becomes `100`, `0.50000000` becomes `0.5`).
+pub fn format_amount(amount: f64) -> String {
+    format_amount_with(amount, AMOUNT_DECIMALS)
+}
+
+/// Like `format_amount` but rounds to `decimals` places.
+pub fn format_amount_with(amount: f64, decimals: usize) -> String {
+    let rendered = format!("{:.*}", decimals, amount);
+    let trimmed = if rendered.contains('.') {
+        rendered.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        rendered.as_str()
+    };
+    match trimmed {
+        "-0" => String::from("0"),
+        other => other.to_string(),
+    }
+}
+
+/// Shortens a hex hash for display to its first 8 and last 4 characters
+/// (`0000abcd…ef12`). Hashes too short to gain anything are returned whole.
+/// For display only; serialized hashes are never abbreviated.
+pub fn abbreviate_hash(hash: &str) -> String {
+    const LEADING: usize = 8;
+    const TRAILING: usize = 4;
+    if hash.len() <= LEADING + TRAILING + 1 || !hash.is_ascii() {
+        return hash.to_string();
+    }
+    format!("{}…{}", &hash[..LEADING], &hash[hash.len() - TRAILING..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_keep_their_smallest_unit_and_drop_trailing_zeros() {
+        assert_eq!(format_amount(0.0), "0");
+        assert_eq!(format_amount(0.00000001), "0.00000001");
+        assert_eq!(format_amount(1.0), "1");
+        assert_eq!(format_amount(0.5), "0.5");
+        assert_eq!(format_amount(-0.000000001), "0");
+    }
+}
@@ -0,0 +1,42 @@
+mod analytics;
+mod block;
+mod bloom;
+mod blockchain;
+mod builder;
+mod clock;
+mod config;
+mod error;
+mod export;
+mod format;
+mod log;
+mod simulation;
+mod sync;
+mod transaction;
+
+pub use analytics::{AddressStats, ChainStats, SupplyInfo};
+pub use block::{
+    meets_difficulty, meets_difficulty_bits, merkle_proof, merkle_root, verify_block_pow, verify_merkle_proof, Block, MerkleProof,
+    BLOCK_HEADER_BYTES,
+};
+pub use bloom::BloomFilter;
+pub use blockchain::{Blockchain, MempoolMerge, ReorgStats, SaveFormat};
+pub use builder::BlockchainBuilder;
+pub use clock::Clock;
+pub use config::{BlockchainConfig, Retarget, RewardRamp, ValidationLevel};
+pub use error::BmsError;
+pub use format::{abbreviate_hash, format_amount, format_amount_with};
+pub use log::LogFormat;
+pub use simulation::{AttackReport, Operation, ReorgReport};
+pub use sync::ChainDiff;
+pub use transaction::{verify_transaction, Transaction, TransactionBuilder, TransactionKind, TxId, TxRejectReason};
+
+pub const DIFFICULTY: usize = 4;
+pub const GENESIS_DIFFICULTY: usize = 1;
+pub const MINING_REWARD: f64 = 100.0;
+pub const HALVING_INTERVAL: u32 = 10;
+pub const CONFIRMATION_DEPTH: u32 = 6;
+pub const AMOUNT_DECIMALS: usize = 8;
+pub const UNSTAKE_COOLDOWN_BLOCKS: u32 = 5;
+pub const MAX_MEMPOOL_PRESSURE: f64 = 10.0;
+pub const DEFAULT_NETWORK_ID: &str = "bms-main";
+pub const MAX_MEMO_BYTES: usize = 80;
@@ -0,0 +1,67 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::format::format_amount;
+use crate::transaction::TxId;
+
+/// How `Blockchain` writes its log events when `verbose` is on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogFormat {
+    /// One human-readable line per event.
+    #[default]
+    Text,
+    /// One JSON object per line with an `event` name, a `timestamp` and
+    /// event-specific fields, for log pipelines.
+    Json,
+}
+
+/// Something worth reporting that happened to a `Blockchain`.
+pub(crate) enum LogEvent<'a> {
+    BlockMined { height: u32, hash: &'a str },
+    RewardHalved { reward: f64 },
+    TxAccepted { tx_id: &'a TxId },
+    TxRejected { from: &'a str, reason: String },
+    Reorg { fork_height: u32, depth: u32, old_tip: &'a str, new_tip: &'a str },
+}
+
+impl LogEvent<'_> {
+    pub(crate) fn render(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Text => self.text(),
+            LogFormat::Json => {
+                let mut object = self.fields();
+                object["timestamp"] = json!(Utc::now().to_rfc3339());
+                object.to_string()
+            }
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            LogEvent::BlockMined { hash, .. } => format!("Block mined: {}", hash),
+            LogEvent::RewardHalved { reward } => format!("Mining reward halved to {} tokens", format_amount(*reward)),
+            LogEvent::TxAccepted { tx_id } => format!("Transaction {} accepted", tx_id),
+            LogEvent::TxRejected { from, reason } => format!("Transaction from {} rejected: {}", from, reason),
+            LogEvent::Reorg { fork_height, depth, old_tip, new_tip } => {
+                format!("Reorg of depth {} at height {}: tip {} replaced by {}", depth, fork_height, old_tip, new_tip)
+            }
+        }
+    }
+
+    fn fields(&self) -> serde_json::Value {
+        match self {
+            LogEvent::BlockMined { height, hash } => json!({ "event": "block_mined", "height": height, "hash": hash }),
+            LogEvent::RewardHalved { reward } => json!({ "event": "reward_halved", "reward": reward }),
+            LogEvent::TxAccepted { tx_id } => json!({ "event": "tx_accepted", "tx_id": tx_id }),
+            LogEvent::TxRejected { from, reason } => json!({ "event": "tx_rejected", "from": from, "reason": reason }),
+            LogEvent::Reorg { fork_height, depth, old_tip, new_tip } => json!({
+                "event": "reorg",
+                "height": fork_height,
+                "depth": depth,
+                "old_tip": old_tip,
+                "new_tip": new_tip,
+            }),
+        }
+    }
+}
@@ -1,177 +1,260 @@
-use sha2::{Digest, Sha256};
-use chrono::Utc;
-use std::collections::HashMap;
-use rand::Rng;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
 
-const DIFFICULTY: usize = 4;
-const MINING_REWARD: f64 = 100.0;
-const HALVING_INTERVAL: u32 = 10;
+use blockchain::{format_amount, Blockchain, BlockchainConfig, BmsError, Transaction, TxId, CONFIRMATION_DEPTH};
+use serde_json::json;
+use signal_hook::consts::SIGINT;
+use signal_hook::iterator::Signals;
 
-#[derive(Clone, Debug)]
-struct Transaction {
-    from: String,
-    to: String,
-    amount: f64,
-}
+/// Where the interactive simulator keeps its chain between runs, and the
+/// chain `pipe` submits to unless given `--chain`.
+const CHAIN_FILE: &str = "chain.json";
 
-impl Transaction {
-    fn new(from: String, to: String, amount: f64) -> Self {
-        Self { from, to, amount }
-    }
-}
+/// The interactive simulator's wallet list, saved beside its chain.
+const WALLET_LIST_FILE: &str = "wallets.csv";
 
-#[derive(Clone)]
-struct Block {
-    index: u32,
-    timestamp: i64,
-    transactions: Vec<Transaction>,
-    previous_hash: String,
-    hash: String,
-    nonce: u32,
-}
+/// Environment variable holding the passphrase chain files are encrypted
+/// with. Unset, chain files are saved and loaded in the clear.
+const PASSPHRASE_VAR: &str = "BMS_PASSPHRASE";
 
-impl Block {
-    fn new(index: u32, transactions: Vec<Transaction>, previous_hash: String) -> Block {
-        let mut block = Block {
-            index,
-            timestamp: Utc::now().timestamp(),
-            transactions,
-            previous_hash,
-            hash: String::new(),
-            nonce: 0,
-        };
-        block.mine();
-        block
-    }
+/// Exit status after saving on Ctrl-C, as a shell reports death by SIGINT.
+const INTERRUPTED_EXIT: i32 = 130;
 
-    fn calculate_hash(&self) -> String {
-        let mut hasher = Sha256::new();
-        let data = format!("{}{}{:?}{}{}", self.index, self.timestamp, &self.transactions, &self.previous_hash, self.nonce);
-        hasher.update(data.as_bytes());
-        format!("{:x}", hasher.finalize())
-    }
+/// A state-changing menu action that "Undo last action" can revert.
+enum Action {
+    Submitted(TxId),
+    Mined(u32),
+}
 
-    fn mine(&mut self) {
-        let target = "0".repeat(DIFFICULTY);
-        while !self.hash.starts_with(&target) {
-            self.nonce += 1;
-            self.hash = self.calculate_hash();
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("pipe") => {
+            let mut strict = false;
+            let mut chain_file = CHAIN_FILE;
+            let mut options = args[1..].iter();
+            while let Some(option) = options.next() {
+                match (option.as_str(), options.clone().next()) {
+                    ("--strict", _) => strict = true,
+                    ("--chain", Some(path)) => {
+                        chain_file = path;
+                        options.next();
+                    }
+                    _ => return usage(option),
+                }
+            }
+            pipe(Path::new(chain_file), strict)
+        }
+        Some("diff") => match &args[1..] {
+            [first, second] => diff(Path::new(first), Path::new(second)),
+            [_, _, extra, ..] => usage(extra),
+            _ => {
+                eprintln!("Usage: blockchain diff <a.json> <b.json>");
+                ExitCode::FAILURE
+            }
+        },
+        _ => {
+            let mut chain_file = CHAIN_FILE;
+            let mut autosave_every = None;
+            let mut options = args.iter();
+            while let Some(option) = options.next() {
+                match (option.as_str(), options.clone().next()) {
+                    ("--chain", Some(path)) => {
+                        chain_file = path;
+                        options.next();
+                    }
+                    ("--autosave-every", Some(count)) => match count.parse::<u32>() {
+                        Ok(count) if count > 0 => {
+                            autosave_every = Some(count);
+                            options.next();
+                        }
+                        _ => return usage(count),
+                    },
+                    _ => return usage(option),
+                }
+            }
+            interactive(Path::new(chain_file), autosave_every)
         }
-        println!("Block mined: {}", self.hash);
     }
 }
 
-struct Blockchain {
-    chain: Vec<Block>,
-    pending_transactions: Vec<Transaction>,
-    wallets: HashMap<String, f64>,
-    current_mining_reward: f64,
+fn usage(unknown: &str) -> ExitCode {
+    eprintln!("Unknown argument: {}", unknown);
+    eprintln!("Usage: blockchain [--chain <path>] [--autosave-every <actions>]");
+    eprintln!("       blockchain pipe [--strict] [--chain <path>]");
+    eprintln!("       blockchain diff <a.json> <b.json>");
+    eprintln!("Set {} to encrypt and decrypt chain files with a passphrase.", PASSPHRASE_VAR);
+    ExitCode::FAILURE
 }
 
-impl Blockchain {
-    fn new() -> Blockchain {
-        let mut blockchain = Blockchain {
-            chain: Vec::new(),
-            pending_transactions: Vec::new(),
-            wallets: HashMap::new(),
-            current_mining_reward: MINING_REWARD,
-        };
-        blockchain.create_genesis_block();
-        blockchain
+/// Loads the chain at `path`, decrypting it if `PASSPHRASE_VAR` is set.
+fn load_chain(path: &Path) -> std::io::Result<Blockchain> {
+    match std::env::var(PASSPHRASE_VAR) {
+        Ok(passphrase) => Blockchain::load_from_file_encrypted(path, &passphrase),
+        Err(_) => Blockchain::load_from_file(path),
     }
+}
 
-    fn create_genesis_block(&mut self) {
-        let genesis_block = Block::new(0, vec![], String::from("0"));
-        self.chain.push(genesis_block);
+/// Saves the chain to `path`, encrypting it if `PASSPHRASE_VAR` is set.
+fn save_chain(blockchain: &Blockchain, path: &Path) -> std::io::Result<()> {
+    match std::env::var(PASSPHRASE_VAR) {
+        Ok(passphrase) => blockchain.save_to_file_encrypted(path, &passphrase),
+        Err(_) => blockchain.save_to_file(path),
     }
+}
 
-    fn create_wallet(&mut self) -> String {
-        let address = format!("0x{:x}", rand::thread_rng().gen::<u64>());
-        self.wallets.insert(address.clone(), 0.0);
-        address
+/// Reads newline-delimited JSON transactions from stdin, submits each to
+/// the chain saved at `path` and writes one JSON result per line to stdout
+/// as it goes; the chain is saved back afterwards with the accepted
+/// transactions pending. Transactions are checked as submitted, signature,
+/// nonce and `chain_tag` included. With `strict`, any rejected or
+/// unparsable line makes the exit status nonzero.
+fn pipe(path: &Path, strict: bool) -> ExitCode {
+    let mut blockchain = match load_chain(path) {
+        Ok(blockchain) => blockchain,
+        Err(err) => {
+            eprintln!("Could not load {}: {}", path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Stdout carries nothing but the results; the saved setting is restored
+    // before saving.
+    let verbose = blockchain.config().verbose;
+    blockchain.set_verbose(false);
+    let mut stdout = std::io::stdout().lock();
+    let mut failed = false;
+    for (number, line) in std::io::stdin().lock().lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("Failed to read stdin: {}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match serde_json::from_str::<Transaction>(&line) {
+            Err(err) => {
+                failed = true;
+                json!({ "line": number + 1, "status": "invalid", "error": err.to_string() })
+            }
+            Ok(transaction) => match blockchain.add_transaction(transaction) {
+                Ok(tx_id) => json!({ "line": number + 1, "status": "accepted", "tx_id": tx_id }),
+                Err(reason) => {
+                    failed = true;
+                    json!({ "line": number + 1, "status": "rejected", "reason": reason.to_string() })
+                }
+            },
+        };
+        if writeln!(stdout, "{}", result).and_then(|()| stdout.flush()).is_err() {
+            return ExitCode::FAILURE;
+        }
     }
-
-    fn get_balance(&self, address: &str) -> f64 {
-        *self.wallets.get(address).unwrap_or(&0.0)
+    blockchain.set_verbose(verbose);
+    if let Err(err) = save_chain(&blockchain, path) {
+        eprintln!("Could not save {}: {}", path.display(), err);
+        return ExitCode::FAILURE;
     }
-
-    fn add_transaction(&mut self, transaction: Transaction) -> bool {
-        if transaction.from != "0" && self.get_balance(&transaction.from) < transaction.amount {
-            return false;
-        }
-        self.pending_transactions.push(transaction);
-        true
+    if strict && failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
     }
+}
 
-    fn mine_pending_transactions(&mut self, miner_address: &str) {
-        let mut transactions_to_mine = self.pending_transactions.clone();
-
-        for tx in &transactions_to_mine {
-            if tx.from != "0" {
-                *self.wallets.entry(tx.from.clone()).or_insert(0.0) -= tx.amount;
+/// Loads two saved chains and reports where they diverge: whether one
+/// extends the other or they fork, the blocks and transactions after the
+/// last common block on each side, and the balances that differ.
+fn diff(first: &Path, second: &Path) -> ExitCode {
+    let mut chains = Vec::with_capacity(2);
+    for path in [first, second] {
+        match load_chain(path) {
+            Ok(blockchain) => chains.push(blockchain),
+            Err(err) => {
+                eprintln!("Could not load {}: {}", path.display(), err);
+                return ExitCode::FAILURE;
             }
-            *self.wallets.entry(tx.to.clone()).or_insert(0.0) += tx.amount;
-        }
-
-        let reward_tx = Transaction::new(String::from("0"), miner_address.to_string(), self.current_mining_reward);
-        transactions_to_mine.push(reward_tx);
-
-        let new_block = Block::new(
-            self.chain.len() as u32,
-            transactions_to_mine,
-            self.chain.last().unwrap().hash.clone(),
-        );
-        self.chain.push(new_block);
-
-        *self.wallets.entry(miner_address.to_string()).or_insert(0.0) += self.current_mining_reward;
-
-        self.pending_transactions.clear();
-
-        if self.chain.len() as u32 % HALVING_INTERVAL == 0 {
-            self.current_mining_reward /= 2.0;
-            println!("Mining reward halved to {} tokens", self.current_mining_reward);
         }
     }
+    print!("{}", chains[0].diff(&chains[1]));
+    ExitCode::SUCCESS
+}
 
-    fn is_chain_valid(&self) -> bool {
-        for i in 1..self.chain.len() {
-            let current_block = &self.chain[i];
-            let previous_block = &self.chain[i - 1];
+/// Saves the chain, with its mempool, to `path` and the wallet list beside
+/// it, confirming each. Returns whether the chain was saved.
+fn save(blockchain: &Blockchain, wallets: &[String], path: &Path) -> bool {
+    if let Err(err) = save_chain(blockchain, path) {
+        eprintln!("Could not save {}: {}", path.display(), err);
+        return false;
+    }
+    println!("Chain saved to {}", path.display());
+    let wallet_list = path.with_file_name(WALLET_LIST_FILE);
+    match blockchain.export_wallet_list(wallets, &wallet_list.to_string_lossy()) {
+        Ok(()) => println!("Wallet list saved to {}", wallet_list.display()),
+        Err(err) => eprintln!("Could not save {}: {}", wallet_list.display(), err),
+    }
+    true
+}
 
-            if current_block.hash != current_block.calculate_hash() {
-                return false;
-            }
+/// Saves the latest snapshot and exits when the user presses Ctrl-C, which
+/// would otherwise discard everything since the last save. The menu loop
+/// keeps the snapshot current after every state-changing action.
+fn save_on_interrupt(snapshot: Arc<Mutex<(Blockchain, Vec<String>)>>, path: PathBuf) -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGINT])?;
+    std::thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            println!();
+            let (blockchain, wallets) = &*snapshot.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let saved = save(blockchain, wallets, &path);
+            std::process::exit(if saved { INTERRUPTED_EXIT } else { 1 });
+        }
+    });
+    Ok(())
+}
 
-            if current_block.previous_hash != previous_block.hash {
-                return false;
+/// Runs the menu-driven simulator, resuming from the chain at `path` if it
+/// exists and saving back to it on exit or Ctrl-C, along with the wallet
+/// list in `WALLET_LIST_FILE` beside it. With `autosave_every`, it also
+/// saves after that many state-changing actions. A chain file that cannot
+/// be loaded is left alone and the simulator does not start; listed wallets
+/// the chain no longer holds keys for are reported and dropped.
+fn interactive(path: &Path, autosave_every: Option<u32>) -> ExitCode {
+    let mut blockchain = if path.exists() {
+        match load_chain(path) {
+            Ok(blockchain) => {
+                println!("Loaded {} block(s) from {}", blockchain.height() + 1, path.display());
+                blockchain
             }
-
-            if !current_block.hash.starts_with(&"0".repeat(DIFFICULTY)) {
-                return false;
+            Err(err) => {
+                eprintln!("Could not load {}: {}", path.display(), err);
+                return ExitCode::FAILURE;
             }
         }
-        true
-    }
-
-    fn print_chain(&self) {
-        for (i, block) in self.chain.iter().enumerate() {
-            println!("Block #{}", i);
-            println!("Hash: {}", block.hash);
-            println!("Previous Hash: {}", block.previous_hash);
-            println!("Transactions: {}", block.transactions.len());
-            for (j, tx) in block.transactions.iter().enumerate() {
-                println!("  Transaction {}: {} tokens from {} to {}", j+1, tx.amount, tx.from, tx.to);
+    } else {
+        Blockchain::with_config(BlockchainConfig { verbose: true, ..BlockchainConfig::default() })
+            .expect("the default configuration is valid")
+    };
+    let mut wallets: Vec<String> = blockchain.wallet_addresses();
+    let wallet_list = path.with_file_name(WALLET_LIST_FILE);
+    if wallet_list.exists() {
+        match blockchain.import_wallet_list(&wallet_list.to_string_lossy()) {
+            Ok((_, missing)) => {
+                for address in missing {
+                    println!("Warning: wallet {} from {} is not in the chain and was dropped", address, wallet_list.display());
+                }
             }
-            println!();
+            Err(err) => eprintln!("Could not read {}: {}", wallet_list.display(), err),
         }
-        println!("Blockchain validity: {}", self.is_chain_valid());
-        println!("Current mining reward: {} tokens", self.current_mining_reward);
     }
-}
-
-fn main() {
-    let mut blockchain = Blockchain::new();
-    let mut wallets: Vec<String> = Vec::new();
+    let snapshot = Arc::new(Mutex::new((blockchain.clone(), wallets.clone())));
+    if let Err(err) = save_on_interrupt(Arc::clone(&snapshot), path.to_path_buf()) {
+        eprintln!("Ctrl-C will not save the chain: {}", err);
+    }
+    let mut unsaved_actions = 0;
+    let mut history: Vec<Action> = Vec::new();
 
     loop {
         println!("1. Create a new wallet");
@@ -179,15 +262,24 @@ fn main() {
         println!("3. Send tokens");
         println!("4. Mine pending transactions");
         println!("5. View blockchain");
-        println!("6. Exit");
+        println!("6. Transaction status");
+        println!("7. View statistics");
+        println!("8. Cancel pending transaction");
+        println!("9. Undo last action");
+        println!("10. Rebuild state from chain");
+        println!("11. Simulate tampering with a block");
+        println!("12. Exit");
 
         let mut choice = String::new();
         std::io::stdin().read_line(&mut choice).expect("Failed to read line");
+        let choice = choice.trim();
 
-        match choice.trim() {
+        match choice {
             "1" => {
                 let new_wallet = blockchain.create_wallet();
                 wallets.push(new_wallet.clone());
+                // Keep the numbered menus stable regardless of creation order.
+                wallets.sort();
                 println!("New wallet created: {}", new_wallet);
             }
             "2" => {
@@ -195,7 +287,7 @@ fn main() {
                     println!("No wallets created yet. Create a wallet first.");
                 } else {
                     for (i, wallet) in wallets.iter().enumerate() {
-                        println!("{}. {}: {} tokens", i + 1, wallet, blockchain.get_balance(wallet));
+                        println!("{}. {}: {} tokens ({} spendable)", i + 1, wallet, format_amount(blockchain.get_balance(wallet)), format_amount(blockchain.spendable_balance(wallet)));
                     }
                     print!("Enter the number of the wallet: ");
                     let mut wallet_choice = String::new();
@@ -203,7 +295,10 @@ fn main() {
                     if let Ok(index) = wallet_choice.trim().parse::<usize>() {
                         if index > 0 && index <= wallets.len() {
                             let wallet = &wallets[index - 1];
-                            println!("Balance of {}: {} tokens", wallet, blockchain.get_balance(wallet));
+                            println!("Balance of {}: {} tokens", wallet, format_amount(blockchain.get_balance(wallet)));
+                            println!("  Spendable: {} tokens", format_amount(blockchain.spendable_balance(wallet)));
+                            println!("  Confirmed ({}+ confirmations): {} tokens", CONFIRMATION_DEPTH, format_amount(blockchain.confirmed_balance(wallet, CONFIRMATION_DEPTH)));
+                            println!("  Pending outgoing: {} tokens", format_amount(blockchain.pending_outgoing(wallet)));
                         } else if index != 0 {
                             println!("Invalid wallet selection");
                         }
@@ -218,7 +313,7 @@ fn main() {
                 } else {
                     println!("Select sender wallet:");
                     for (i, wallet) in wallets.iter().enumerate() {
-                        println!("{}. {}: {} tokens", i + 1, wallet, blockchain.get_balance(wallet));
+                        println!("{}. {}: {} tokens ({} spendable)", i + 1, wallet, format_amount(blockchain.get_balance(wallet)), format_amount(blockchain.spendable_balance(wallet)));
                     }
                     print!("Choose sender (enter the number): ");
                     let mut sender_choice = String::new();
@@ -243,13 +338,24 @@ fn main() {
                                     print!("Enter amount to send: ");
                                     let mut amount_str = String::new();
                                     std::io::stdin().read_line(&mut amount_str).expect("Failed to read line");
-                                    if let Ok(amount) = amount_str.trim().parse::<f64>() {
-                                        let transaction = Transaction::new(sender.clone(), recipient, amount);
-                                        if blockchain.add_transaction(transaction) {
-                                            println!("Transaction added to pending transactions");
-                                            println!("Note: this txn will be processed when the next block is mined.");
-                                        } else {
-                                            println!("Transaction failed: Insufficient balance");
+                                    if let Some(amount) = amount_str.trim().parse::<f64>().ok().filter(|amount| amount.is_finite() && *amount > 0.0) {
+                                        print!("Enter fee (leave empty for none): ");
+                                        let mut fee_str = String::new();
+                                        std::io::stdin().read_line(&mut fee_str).expect("Failed to read line");
+                                        // An unparsable fee is left for add_transaction to reject.
+                                        let fee = match fee_str.trim() {
+                                            "" => 0.0,
+                                            fee => fee.parse::<f64>().unwrap_or(f64::NAN),
+                                        };
+                                        let mut transaction = blockchain.new_transaction(&sender, &recipient, amount).fee(fee).build();
+                                        blockchain.sign_with_wallet(&mut transaction);
+                                        match blockchain.add_transaction(transaction) {
+                                            Ok(tx_id) => {
+                                                println!("Transaction {} added to pending transactions", tx_id);
+                                                history.push(Action::Submitted(tx_id));
+                                                println!("Note: this txn will be processed when the next block is mined.");
+                                            }
+                                            Err(reason) => println!("Transaction failed: {}", reason),
                                         }
                                     } else {
                                         println!("Invalid amount");
@@ -282,9 +388,32 @@ fn main() {
                     if let Ok(index) = miner_choice.trim().parse::<usize>() {
                         if index > 0 && index <= wallets.len() {
                             let miner = &wallets[index - 1];
-                            blockchain.mine_pending_transactions(miner);
-                            println!("Block mined and added to the blockchain");
-                            println!("Miner {} received {} tokens as reward", miner, blockchain.current_mining_reward);
+                            print!("Enter a coinbase message (leave empty for none): ");
+                            let mut message = String::new();
+                            std::io::stdin().read_line(&mut message).expect("Failed to read line");
+                            let message = message.trim();
+                            let mined = if message.is_empty() {
+                                blockchain.mine_pending_transactions(miner)
+                            } else {
+                                blockchain.mine_pending_transactions_with_message(miner, message)
+                            };
+                            let block = match mined {
+                                Ok(block) => block,
+                                Err(BmsError::NothingToMine) => {
+                                    println!("Mining skipped: the mempool is empty and empty blocks are disabled");
+                                    continue;
+                                }
+                                Err(err) => {
+                                    println!("Mining failed: {}", err);
+                                    continue;
+                                }
+                            };
+                            println!("Block #{} mined and added to the blockchain", block.index);
+                            println!("Miner {} received {} tokens as reward", miner, format_amount(block.transactions[0].amount));
+                            history.push(Action::Mined(block.index));
+                            for tx in blockchain.last_evicted() {
+                                println!("Evicted expired transaction {} ({} tokens from {} to {})", tx.id(), format_amount(tx.amount), tx.from, tx.to);
+                            }
                         } else {
                             println!("Invalid miner selection");
                         }
@@ -294,13 +423,123 @@ fn main() {
                 }
             }
             "5" => {
-                blockchain.print_chain();
+                print!("Show full hashes? (y/N): ");
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer).expect("Failed to read line");
+                blockchain.print_chain_with(answer.trim().eq_ignore_ascii_case("y"));
             }
             "6" => {
+                print!("Enter the transaction ID: ");
+                let mut tx_id = String::new();
+                std::io::stdin().read_line(&mut tx_id).expect("Failed to read line");
+                let tx_id = tx_id.trim();
+                match blockchain.confirmations(tx_id) {
+                    None => println!("Unknown transaction"),
+                    Some(0) => println!("Transaction {} is pending (0 confirmations)", tx_id),
+                    Some(confirmations) => {
+                        println!("Transaction {} has {} confirmation(s)", tx_id, confirmations);
+                        if blockchain.is_confirmed(tx_id, CONFIRMATION_DEPTH) {
+                            println!("Confirmed (required depth: {})", CONFIRMATION_DEPTH);
+                        } else {
+                            println!("Not yet confirmed (required depth: {})", CONFIRMATION_DEPTH);
+                        }
+                    }
+                }
+            }
+            "7" => {
+                print!("Output as JSON? (y/N): ");
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer).expect("Failed to read line");
+                if answer.trim().eq_ignore_ascii_case("y") {
+                    let stats = serde_json::to_string_pretty(&blockchain.stats()).expect("stats serialize to JSON");
+                    println!("{}", stats);
+                    continue;
+                }
+                println!("Height: {}", blockchain.height());
+                println!("Wallets: {}", blockchain.wallet_count());
+                println!("Unique addresses in chain: {}", blockchain.unique_addresses_in_chain());
+                println!("Total supply: {} tokens", format_amount(blockchain.cumulative_supply_at_height(blockchain.height())));
+                println!("Current mining reward: {} tokens", format_amount(blockchain.current_mining_reward()));
+                println!("Difficulty: {} leading zero bits", blockchain.next_target_bits());
+                println!("Next halving in {} blocks", blockchain.blocks_until_next_halving());
+                for (height, reward) in blockchain.halving_schedule(3) {
+                    println!("  Block #{}: {} tokens", height, format_amount(reward));
+                }
+                let reorgs = blockchain.reorg_stats();
+                println!("Reorgs: {} (deepest {} blocks)", reorgs.count, reorgs.max_depth);
+                let pressure = blockchain.mempool_pressure();
+                let congestion = if pressure > 1.0 { "congested" } else { "clear" };
+                println!("Pending transactions: {} (mempool pressure {:.2}, {})", blockchain.pending_transactions().len(), pressure, congestion);
+            }
+            "8" => {
+                print!("Enter the transaction ID to cancel: ");
+                let mut tx_id = String::new();
+                std::io::stdin().read_line(&mut tx_id).expect("Failed to read line");
+                let tx_id = tx_id.trim();
+                match blockchain.cancel_with_wallet(tx_id) {
+                    Ok(true) => println!("Transaction {} cancelled", tx_id),
+                    Ok(false) if blockchain.contains_tx(tx_id) => println!("Transaction {} is already mined and cannot be cancelled", tx_id),
+                    Ok(false) => println!("No pending transaction with ID {}", tx_id),
+                    Err(reason) => println!("Cannot cancel: {}", reason),
+                }
+            }
+            "9" => match history.pop() {
+                None => println!("Nothing to undo"),
+                Some(Action::Submitted(tx_id)) => match blockchain.remove_pending_transaction(&tx_id) {
+                    Some(tx) => println!("Undone: transaction of {} tokens from {} to {} removed from pending", format_amount(tx.amount), tx.from, tx.to),
+                    None => println!("Transaction {} is no longer pending; nothing undone", tx_id),
+                },
+                Some(Action::Mined(height)) => {
+                    if blockchain.height() != height {
+                        println!("Block #{} is no longer the tip; nothing undone", height);
+                    } else if let Some(block) = blockchain.undo_last_block() {
+                        let returned = block.transactions.iter().filter(|tx| !tx.is_coinbase()).count();
+                        println!("Undone: block #{} removed, {} transaction(s) returned to pending", block.index, returned);
+                    }
+                }
+            },
+            "10" => {
+                let changes = blockchain.replay_state();
+                println!("State rebuilt from {} block(s)", blockchain.height() + 1);
+                if changes.is_empty() {
+                    println!("All balances matched the chain");
+                }
+                for (address, before, after) in changes {
+                    println!("  {}: {} -> {} tokens", address, format_amount(before), format_amount(after));
+                }
+            }
+            "11" => {
+                print!("Enter the height of the block to tamper with: ");
+                let mut height = String::new();
+                std::io::stdin().read_line(&mut height).expect("Failed to read line");
+                print!("Enter the forged amount: ");
+                let mut amount = String::new();
+                std::io::stdin().read_line(&mut amount).expect("Failed to read line");
+                match (height.trim().parse::<u32>(), amount.trim().parse::<f64>()) {
+                    (Ok(height), Ok(amount)) => match blockchain.simulate_attack(height, amount) {
+                        Ok(report) => print!("{}", report),
+                        Err(err) => println!("Cannot simulate: {}", err),
+                    },
+                    _ => println!("Invalid input"),
+                }
+            }
+            "12" => {
+                if !save(&blockchain, &wallets, path) {
+                    return ExitCode::FAILURE;
+                }
                 println!("Exiting the Blockchain Simulator...");
                 break;
             }
-            _ => println!("Invalid option. Please choose a number between 1 and 6."),
+            _ => println!("Invalid option. Please choose a number between 1 and 12."),
+        }
+
+        if matches!(choice, "1" | "3" | "4" | "8" | "9" | "10") {
+            *snapshot.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = (blockchain.clone(), wallets.clone());
+            unsaved_actions += 1;
+            if autosave_every.is_some_and(|every| unsaved_actions >= every) && save(&blockchain, &wallets, path) {
+                unsaved_actions = 0;
+            }
         }
     }
+    ExitCode::SUCCESS
 }
\ No newline at end of file
@@ -0,0 +1,294 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::blockchain::Blockchain;
+use crate::error::BmsError;
+use crate::format::format_amount;
+use crate::transaction::{Transaction, TxId};
+
+const SIMULATION_SEED: u64 = 42;
+
+/// One step of a scripted scenario executed by [`Blockchain::simulate`].
+/// Wallets are referred to by name; the simulator maps each name to the
+/// address created for it.
+#[derive(Clone, Debug)]
+pub enum Operation {
+    CreateWallet(String),
+    /// Genesis allocation; only valid before the first block is mined.
+    Fund(String, f64),
+    Send { from: String, to: String, amount: f64 },
+    Mine(String),
+    /// Reports the wallet's confirmed balance.
+    Balance(String),
+}
+
+/// What a reorg forced by [`Blockchain::simulate_reorg`] changed.
+#[derive(Clone, Debug)]
+pub struct ReorgReport {
+    /// Height of the last block both branches share.
+    pub fork_height: u32,
+    pub old_tip: String,
+    pub new_tip: String,
+    /// Transactions un-mined by the reorg and returned to the mempool.
+    pub requeued: Vec<TxId>,
+    /// Addresses whose balance changed, with the balance before and after.
+    pub balance_changes: Vec<(String, f64, f64)>,
+}
+
+impl fmt::Display for ReorgReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Reorg at height {}: tip {} -> {}", self.fork_height, self.old_tip, self.new_tip)?;
+        writeln!(f, "Returned to mempool: {}", self.requeued.len())?;
+        for tx_id in &self.requeued {
+            writeln!(f, "  {}", tx_id)?;
+        }
+        writeln!(f, "Balance changes: {}", self.balance_changes.len())?;
+        for (address, before, after) in &self.balance_changes {
+            writeln!(f, "  {}: {} -> {}", address, format_amount(*before), format_amount(*after))?;
+        }
+        Ok(())
+    }
+}
+
+/// What [`Blockchain::simulate_attack`] found when it tampered with a block.
+#[derive(Debug)]
+pub struct AttackReport {
+    pub height: u32,
+    pub tx_id: TxId,
+    pub original_amount: f64,
+    pub forged_amount: f64,
+    /// Why validation rejected the chain with the forged amount left under
+    /// the block's original hash.
+    pub tampered: BmsError,
+    /// Why validation rejected the chain after the forged block was mined
+    /// again; `None` if it passed, which is only possible at the tip since
+    /// nothing links to it.
+    pub remined: Option<BmsError>,
+}
+
+impl fmt::Display for AttackReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Changed transaction {} in block #{} from {} to {} tokens",
+            self.tx_id,
+            self.height,
+            format_amount(self.original_amount),
+            format_amount(self.forged_amount)
+        )?;
+        writeln!(f, "Detected: {}", self.tampered)?;
+        match &self.remined {
+            Some(err) => writeln!(f, "After re-mining the block: {}", err),
+            None => writeln!(f, "After re-mining the block: accepted, as no later block links to the tip"),
+        }
+    }
+}
+
+impl Blockchain {
+    /// Demonstrates tamper detection on a copy of the chain: changes the
+    /// amount of a transaction in block `height` (its first transfer, or the
+    /// coinbase if it has none) to `forged_amount`, validates the copy, then
+    /// re-mines the forged block and validates again. The chain itself is
+    /// left untouched.
+    pub fn simulate_attack(&self, height: u32, forged_amount: f64) -> Result<AttackReport, BmsError> {
+        if height == 0 || height > self.height() {
+            return Err(BmsError::InvalidConfig(format!(
+                "attacked height {} must be between 1 and the tip at {}",
+                height,
+                self.height()
+            )));
+        }
+
+        let mut blocks = self.blocks().to_vec();
+        let transactions = &mut blocks[height as usize].transactions;
+        let position = transactions.iter().position(|tx| !tx.is_coinbase()).unwrap_or(0);
+        let transaction = &mut transactions[position];
+        if transaction.amount == forged_amount {
+            return Err(BmsError::InvalidConfig(String::from("forged amount must differ from the original")));
+        }
+        let tx_id = transaction.id();
+        let original_amount = transaction.amount;
+        transaction.amount = forged_amount;
+        let tampered = match self.validate_chain(&blocks) {
+            Err(err) => err,
+            Ok(()) => unreachable!("a changed transaction always breaks the merkle root"),
+        };
+
+        let forged = blocks[height as usize].clone();
+        blocks[height as usize] = self.seal_block(forged.index, forged.timestamp, forged.transactions, forged.previous_hash);
+        let remined = self.validate_chain(&blocks).err();
+
+        Ok(AttackReport { height, tx_id, original_amount, forged_amount, tampered, remined })
+    }
+
+    /// Forks the chain after block `fork_height`, mines a competing branch
+    /// holding `transactions` until it is one block longer than the current
+    /// chain, and adopts it with `replace_chain`. Blocks on the branch are
+    /// mined by `miner`; pending transactions are not carried over to it.
+    /// Transactions from wallets this chain created are renumbered with the
+    /// branch's nonces and re-signed; any others must already be valid there.
+    pub fn simulate_reorg(
+        &mut self,
+        fork_height: u32,
+        transactions: Vec<Transaction>,
+        miner: &str,
+    ) -> Result<ReorgReport, BmsError> {
+        if fork_height >= self.height() {
+            return Err(BmsError::InvalidConfig(format!(
+                "fork height {} must be below the tip at {}",
+                fork_height,
+                self.height()
+            )));
+        }
+
+        let mut branch = self.clone();
+        while branch.height() > fork_height {
+            branch.undo_last_block();
+        }
+        let pending: Vec<TxId> = branch.pending_transactions().iter().map(Transaction::id).collect();
+        for tx_id in pending {
+            branch.remove_pending_transaction(&tx_id);
+        }
+        for mut transaction in transactions {
+            let mut renumbered = transaction.clone();
+            renumbered.nonce = branch.next_nonce(&transaction.from);
+            if branch.sign_with_wallet(&mut renumbered) {
+                transaction = renumbered;
+            }
+            branch.add_transaction(transaction)?;
+        }
+        loop {
+            let waiting = branch.pending_transactions().len();
+            branch.mine_pending_transactions(miner)?;
+            let remaining = branch.pending_transactions().len();
+            // Stop once longer, even if some transaction never fits a block.
+            if branch.height() > self.height() && (remaining == 0 || remaining == waiting) {
+                break;
+            }
+        }
+
+        let addresses: BTreeSet<String> = self
+            .blocks()
+            .iter()
+            .chain(branch.blocks())
+            .flat_map(|block| &block.transactions)
+            .flat_map(|tx| [tx.from.clone(), tx.to.clone()])
+            .filter(|address| address != "0")
+            .collect();
+        let before: Vec<f64> = addresses.iter().map(|address| self.get_balance(address)).collect();
+        let pending_before: BTreeSet<TxId> = self.pending_transactions().iter().map(Transaction::id).collect();
+        let old_tip = self.latest_block().hash.clone();
+
+        self.replace_chain(branch.blocks().to_vec())?;
+
+        let requeued = self
+            .pending_transactions()
+            .iter()
+            .map(Transaction::id)
+            .filter(|tx_id| !pending_before.contains(tx_id))
+            .collect();
+        let balance_changes = addresses
+            .into_iter()
+            .zip(before)
+            .map(|(address, before)| {
+                let after = self.get_balance(&address);
+                (address, before, after)
+            })
+            .filter(|(_, before, after)| before != after)
+            .collect();
+        Ok(ReorgReport {
+            fork_height,
+            old_tip,
+            new_tip: self.latest_block().hash.clone(),
+            requeued,
+            balance_changes,
+        })
+    }
+
+    /// Runs `script` in order against a fresh deterministic chain and returns
+    /// one transcript line per operation.
+    pub fn simulate(script: &[Operation]) -> Vec<String> {
+        let mut blockchain = Blockchain::deterministic(SIMULATION_SEED);
+        let mut addresses: HashMap<String, String> = HashMap::new();
+        let mut transcript = Vec::new();
+
+        for operation in script {
+            let line = match operation {
+                Operation::CreateWallet(name) => {
+                    if addresses.contains_key(name) {
+                        format!("create {}: wallet already exists", name)
+                    } else {
+                        let address = blockchain.create_wallet();
+                        let line = format!("create {}: {}", name, address);
+                        addresses.insert(name.clone(), address);
+                        line
+                    }
+                }
+                Operation::Fund(name, amount) => match addresses.get(name) {
+                    None => format!("fund {}: unknown wallet", name),
+                    Some(address) => match blockchain.fund_genesis(address, *amount) {
+                        Ok(()) => format!("fund {}: {} tokens allocated at genesis", name, format_amount(*amount)),
+                        Err(err) => format!("fund {}: {}", name, err),
+                    },
+                },
+                Operation::Send { from, to, amount } => match (addresses.get(from), addresses.get(to)) {
+                    (Some(sender), Some(recipient)) => {
+                        let mut transaction = blockchain.new_transaction(sender, recipient, *amount).build();
+                        blockchain.sign_with_wallet(&mut transaction);
+                        match blockchain.add_transaction(transaction) {
+                            Ok(tx_id) => format!("send {} -> {} {}: pending {}", from, to, format_amount(*amount), tx_id),
+                            Err(reason) => format!("send {} -> {} {}: rejected ({})", from, to, format_amount(*amount), reason),
+                        }
+                    }
+                    _ => format!("send {} -> {} {}: unknown wallet", from, to, format_amount(*amount)),
+                },
+                Operation::Mine(name) => match addresses.get(name) {
+                    None => format!("mine {}: unknown wallet", name),
+                    Some(address) => {
+                        let address = address.clone();
+                        match blockchain.mine_pending_transactions(&address) {
+                            Ok(block) => format!("mine {}: block #{} {}", name, block.index, block.hash),
+                            Err(err) => format!("mine {}: {}", name, err),
+                        }
+                    }
+                },
+                Operation::Balance(name) => match addresses.get(name) {
+                    None => format!("balance {}: unknown wallet", name),
+                    Some(address) => format!("balance {}: {} tokens", name, format_amount(blockchain.get_balance(address))),
+                },
+            };
+            transcript.push(line);
+        }
+        transcript
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transcript_formats_amounts_and_reports_final_balances() {
+        let name = String::from;
+        let script = [
+            Operation::CreateWallet(name("alice")),
+            Operation::CreateWallet(name("bob")),
+            Operation::Fund(name("alice"), 100.0),
+            Operation::Send { from: name("alice"), to: name("bob"), amount: 0.1 + 0.2 },
+            Operation::Send { from: name("bob"), to: name("alice"), amount: 5.0 },
+            Operation::Send { from: name("carol"), to: name("bob"), amount: 1.0 },
+            Operation::Mine(name("bob")),
+            Operation::Balance(name("alice")),
+            Operation::Balance(name("bob")),
+        ];
+        let transcript = Blockchain::simulate(&script);
+        assert_eq!(transcript.len(), script.len());
+        assert_eq!(transcript[2], "fund alice: 100 tokens allocated at genesis");
+        assert!(transcript[3].starts_with("send alice -> bob 0.3: pending "), "{}", transcript[3]);
+        assert_eq!(transcript[4], "send bob -> alice 5: rejected (Insufficient balance)");
+        assert_eq!(transcript[5], "send carol -> bob 1: unknown wallet");
+        assert!(transcript[6].starts_with("mine bob: block #1 "), "{}", transcript[6]);
+        assert_eq!(transcript[7], "balance alice: 99.7 tokens");
+        assert_eq!(transcript[8], "balance bob: 100.3 tokens");
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::error::BmsError;
+use crate::format::format_amount;
+use crate::transaction::TxId;
+
+/// How two chains differ, as returned by [`Blockchain::diff`]: "ours" is
+/// the chain `diff` was called on and "theirs" its argument.
+#[derive(Clone)]
+pub struct ChainDiff {
+    /// Height of the last block both chains share; `None` if not even the
+    /// genesis block matches.
+    pub fork_height: Option<u32>,
+    /// Blocks after the fork point, on each side.
+    pub ours: Vec<Block>,
+    pub theirs: Vec<Block>,
+    /// Non-coinbase transactions mined on only one side of the fork.
+    pub ours_only: Vec<TxId>,
+    pub theirs_only: Vec<TxId>,
+    /// Addresses touched after the fork whose balances differ, with the
+    /// balance on our side and on theirs.
+    pub balance_deltas: Vec<(String, f64, f64)>,
+}
+
+impl ChainDiff {
+    /// Whether one chain is a strict prefix of the other, so the longer one
+    /// extends it rather than forking from it.
+    pub fn is_extension(&self) -> bool {
+        self.fork_height.is_some() && self.ours.is_empty() != self.theirs.is_empty()
+    }
+}
+
+impl fmt::Display for ChainDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fork_height = match self.fork_height {
+            Some(height) => height,
+            None => return writeln!(f, "The chains do not share a genesis block"),
+        };
+        match (self.ours.len(), self.theirs.len()) {
+            (0, 0) => return writeln!(f, "The chains are identical up to block #{}", fork_height),
+            (0, theirs) => writeln!(f, "Second chain extends the first by {} block(s) after block #{}", theirs, fork_height)?,
+            (ours, 0) => writeln!(f, "First chain extends the second by {} block(s) after block #{}", ours, fork_height)?,
+            (ours, theirs) => {
+                writeln!(f, "The chains fork after block #{}: {} block(s) on the first, {} on the second", fork_height, ours, theirs)?
+            }
+        }
+        for (side, blocks, only) in [("first", &self.ours, &self.ours_only), ("second", &self.theirs, &self.theirs_only)] {
+            for block in blocks {
+                writeln!(f, "  {} #{} {} ({} transactions)", side, block.index, block.hash, block.transactions.len())?;
+            }
+            for tx_id in only {
+                writeln!(f, "  only on the {}: transaction {}", side, tx_id)?;
+            }
+        }
+        for (address, ours, theirs) in &self.balance_deltas {
+            writeln!(f, "  {}: {} -> {} tokens", address, format_amount(*ours), format_amount(*theirs))?;
+        }
+        Ok(())
+    }
+}
+
+impl Blockchain {
+    /// Block hashes from genesis to tip; the lightweight "headers" a peer
+    /// exchanges before deciding which blocks it needs.
+    pub fn block_hashes(&self) -> Vec<String> {
+        self.blocks().iter().map(|block| block.hash.clone()).collect()
+    }
+
+    /// Height of the last block shared with a peer whose chain has the given
+    /// hashes, found by walking back from the lower of the two tips. `None`
+    /// means the chains do not even share a genesis block.
+    pub fn find_fork_point(&self, peer_hashes: &[String]) -> Option<u32> {
+        let blocks = self.blocks();
+        let common_len = blocks.len().min(peer_hashes.len());
+        (0..common_len)
+            .rev()
+            .find(|&height| blocks[height].hash == peer_hashes[height])
+            .map(|height| height as u32)
+    }
+
+    /// Reconciles with a peer that has a different tip. Only the blocks after
+    /// the last common ancestor are taken from the peer; they are spliced
+    /// onto our prefix and the whole result is validated by `replace_chain`
+    /// before it is adopted. Peers on a different `chain_id` are refused
+    /// outright. Returns whether our chain changed.
+    pub fn sync_from(&mut self, peer: &Blockchain) -> Result<bool, BmsError> {
+        let (ours, theirs) = (self.chain_id(), peer.chain_id());
+        if ours != theirs {
+            return Err(BmsError::ChainIdMismatch { ours, theirs });
+        }
+        if peer.height() <= self.height() {
+            return Ok(false);
+        }
+        let fork_point = match self.find_fork_point(&peer.block_hashes()) {
+            Some(height) => height as usize,
+            None => return Err(BmsError::GenesisMismatch),
+        };
+
+        let missing = &peer.blocks()[fork_point + 1..];
+        let mut spliced: Vec<Block> = self.blocks()[..=fork_point].to_vec();
+        spliced.extend_from_slice(missing);
+        self.replace_chain(spliced)?;
+        Ok(true)
+    }
+
+    /// Compares this chain with `other` from their last common block:
+    /// the blocks and transactions on each side of the fork and the
+    /// balances that end up different.
+    pub fn diff(&self, other: &Blockchain) -> ChainDiff {
+        let fork_height = self.find_fork_point(&other.block_hashes());
+        let after_fork = fork_height.map_or(0, |height| height as usize + 1);
+        let ours = self.blocks()[after_fork..].to_vec();
+        let theirs = other.blocks()[after_fork..].to_vec();
+        let mined_ids = |blocks: &[Block]| -> Vec<TxId> {
+            blocks.iter().flat_map(|block| &block.transactions).filter(|tx| !tx.is_coinbase()).map(|tx| tx.id()).collect()
+        };
+        let (our_ids, their_ids) = (mined_ids(&ours), mined_ids(&theirs));
+        let our_set: HashSet<&TxId> = our_ids.iter().collect();
+        let their_set: HashSet<&TxId> = their_ids.iter().collect();
+        let ours_only = our_ids.iter().filter(|tx_id| !their_set.contains(tx_id)).cloned().collect();
+        let theirs_only = their_ids.iter().filter(|tx_id| !our_set.contains(tx_id)).cloned().collect();
+        let touched: BTreeSet<&str> = ours
+            .iter()
+            .chain(&theirs)
+            .flat_map(|block| &block.transactions)
+            .flat_map(|tx| [tx.from.as_str(), tx.to.as_str()])
+            .filter(|address| *address != "0")
+            .collect();
+        let balance_deltas = touched
+            .into_iter()
+            .map(|address| (address.to_string(), self.get_balance(address), other.get_balance(address)))
+            .filter(|(_, ours, theirs)| ours != theirs)
+            .collect();
+        ChainDiff { fork_height, ours, theirs, ours_only, theirs_only, balance_deltas }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::BlockchainBuilder;
+    use crate::clock::Clock;
+
+    #[test]
+    fn diff_tells_an_extension_from_a_fork() {
+        let mut base = BlockchainBuilder::new().difficulty(1).clock(Clock::manual(0, 1)).seed(1).build().unwrap();
+        let (alice, bob, miner) = (base.create_wallet(), base.create_wallet(), base.create_wallet());
+        base.fund_genesis(&alice, 100.0).unwrap();
+        base.mine_until_height(2, &miner);
+
+        let mut longer = base.clone();
+        longer.mine_until_height(4, &miner);
+        let extension = base.diff(&longer);
+        assert!(extension.is_extension());
+        assert_eq!(extension.fork_height, Some(2));
+        assert_eq!((extension.ours.len(), extension.theirs.len()), (0, 2));
+        assert!(extension.to_string().starts_with("Second chain extends the first by 2 block(s) after block #2"));
+
+        let mut fork = base.clone();
+        let rival = fork.create_wallet();
+        let mut transfer = fork.new_transaction(&alice, &bob, 25.0).build();
+        fork.sign_with_wallet(&mut transfer);
+        let tx_id = fork.add_transaction(transfer).unwrap();
+        fork.mine_pending_transactions(&rival).unwrap();
+        let diff = longer.diff(&fork);
+        assert!(!diff.is_extension());
+        assert_eq!(diff.fork_height, Some(2));
+        assert_eq!((diff.ours.len(), diff.theirs.len()), (2, 1));
+        assert_eq!((diff.ours_only.clone(), diff.theirs_only.clone()), (Vec::new(), vec![tx_id]));
+        // Sorted by address, as `diff` lists them.
+        let mut expected = vec![
+            (alice.clone(), 100.0, 75.0),
+            (bob.clone(), 0.0, 25.0),
+            (miner.clone(), 400.0, 200.0),
+            (rival.clone(), 0.0, 100.0),
+        ];
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(diff.balance_deltas, expected);
+        assert!(diff.to_string().starts_with("The chains fork after block #2: 2 block(s) on the first, 1 on the second"));
+    }
+}
@@ -0,0 +1,392 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::format::format_amount;
+
+/// Hex-encoded SHA-256 of a transaction's canonical bytes.
+pub type TxId = String;
+
+/// The address owned by an Ed25519 `public_key`: `0x` followed by the first
+/// eight bytes of its SHA-256, in hex.
+pub fn address_of(public_key: &[u8]) -> String {
+    let digest = Sha256::digest(public_key);
+    let hex: String = digest[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("0x{}", hex)
+}
+
+/// Whether `transaction` carries a valid signature by `public_key` over
+/// its `canonical_bytes`, without any chain state. Unlike
+/// `Transaction::verify`, this does not tie the key to the `from` address.
+pub fn verify_transaction(transaction: &Transaction, public_key: &VerifyingKey) -> bool {
+    match Signature::from_slice(&transaction.signature) {
+        Ok(signature) => public_key.verify_strict(&transaction.canonical_bytes(), &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// What a transaction does with its amount.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionKind {
+    /// Moves liquid coins from `from` to `to`.
+    #[default]
+    Transfer,
+    /// Locks liquid coins of `from` as stake. They can no longer be spent
+    /// but count toward the staked balance.
+    Stake,
+    /// Starts unlocking staked coins of `from`; they return to the liquid
+    /// balance `UNSTAKE_COOLDOWN_BLOCKS` blocks after the unstake is mined.
+    Unstake,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Transaction {
+    pub from: String,
+    pub to: String,
+    pub amount: f64,
+    /// Paid by the sender on top of `amount` and collected by the miner of
+    /// the block that includes the transaction. Always 0 for coinbase
+    /// transactions.
+    #[serde(default)]
+    pub fee: f64,
+    /// Per-sender sequence number: each transaction must carry one past the
+    /// sender's previous one, starting at 0, so a signed transaction cannot
+    /// be replayed. Coinbase transactions use the block height instead.
+    #[serde(default)]
+    pub nonce: u64,
+    #[serde(default)]
+    pub kind: TransactionKind,
+    /// `chain_id` of the chain this transaction is meant for, so it cannot
+    /// be replayed on another chain. Empty for coinbase transactions.
+    #[serde(default)]
+    pub chain_tag: String,
+    /// Latest block timestamp this transaction may be mined at. Once a
+    /// block would be stamped later, the transaction is dropped instead.
+    pub expires_at: Option<i64>,
+    /// Free-form text, such as the message a miner embeds in its coinbase.
+    /// Limited to `BlockchainConfig::max_memo_bytes`.
+    pub memo: Option<String>,
+    /// Ed25519 key of the sender; `from` must be its `address_of`. Empty
+    /// until the transaction is signed, and always for coinbase transactions.
+    #[serde(default)]
+    pub public_key: Vec<u8>,
+    /// Ed25519 signature over `canonical_bytes`, set by `sign`.
+    #[serde(default)]
+    pub signature: Vec<u8>,
+}
+
+impl Transaction {
+    /// Starts a transfer of `amount` from `from` to `to`; everything else
+    /// is set on the returned builder.
+    pub fn builder(from: String, to: String, amount: f64) -> TransactionBuilder {
+        TransactionBuilder { transaction: Transaction::new(from, to, amount) }
+    }
+
+    pub fn new(from: String, to: String, amount: f64) -> Self {
+        Self::with_kind(from, to, amount, 0, TransactionKind::Transfer)
+    }
+
+    pub fn coinbase(to: String, amount: f64, nonce: u64) -> Self {
+        Self::with_kind(String::from("0"), to, amount, nonce, TransactionKind::Transfer)
+    }
+
+    pub fn stake(address: String, amount: f64) -> Self {
+        Self::with_kind(address.clone(), address, amount, 0, TransactionKind::Stake)
+    }
+
+    pub fn unstake(address: String, amount: f64) -> Self {
+        Self::with_kind(address.clone(), address, amount, 0, TransactionKind::Unstake)
+    }
+
+    fn with_kind(from: String, to: String, amount: f64, nonce: u64, kind: TransactionKind) -> Self {
+        Self {
+            from,
+            to,
+            amount,
+            fee: 0.0,
+            nonce,
+            kind,
+            chain_tag: String::new(),
+            expires_at: None,
+            memo: None,
+            public_key: Vec::new(),
+            signature: Vec::new(),
+        }
+    }
+
+    /// Tags the transaction for the chain with the given `chain_id`.
+    pub fn for_chain(mut self, chain_id: String) -> Self {
+        self.chain_tag = chain_id;
+        self
+    }
+
+    /// Sets the timestamp after which the transaction can no longer be mined.
+    pub fn expires_at(mut self, timestamp: i64) -> Self {
+        self.expires_at = Some(timestamp);
+        self
+    }
+
+    pub fn with_memo(mut self, memo: String) -> Self {
+        self.memo = Some(memo);
+        self
+    }
+
+    /// Whether the transaction may no longer go into a block stamped
+    /// `timestamp`.
+    pub fn is_expired_at(&self, timestamp: i64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at < timestamp)
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.from == "0"
+    }
+
+    /// Liquid coins the sender parts with: amount plus fee for transfers and
+    /// stakes, only the fee for unstakes, which draw the amount from stake.
+    pub fn liquid_cost(&self) -> f64 {
+        match self.kind {
+            TransactionKind::Transfer | TransactionKind::Stake => self.amount + self.fee,
+            TransactionKind::Unstake => self.fee,
+        }
+    }
+
+    /// Signs the transaction as the owner of `signing_key`. Set every other
+    /// field first: changing any of them afterwards voids the signature.
+    pub fn sign(&mut self, signing_key: &SigningKey) {
+        self.public_key = signing_key.verifying_key().to_bytes().to_vec();
+        self.signature = signing_key.sign(&self.canonical_bytes()).to_bytes().to_vec();
+    }
+
+    /// Whether the transaction carries a valid signature by the owner of
+    /// `from`. Coinbase transactions are never signed and always fail.
+    pub fn verify(&self) -> bool {
+        if address_of(&self.public_key) != self.from {
+            return false;
+        }
+        let Ok(public_key) = <[u8; 32]>::try_from(self.public_key.as_slice()) else {
+            return false;
+        };
+        match VerifyingKey::from_bytes(&public_key) {
+            Ok(verifying_key) => verify_transaction(self, &verifying_key),
+            Err(_) => false,
+        }
+    }
+
+    /// Length-prefixed encoding of every field but the signature, used as
+    /// the input to `id` and as the message `sign` signs.
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for field in [self.from.as_bytes(), self.to.as_bytes(), self.chain_tag.as_bytes(), &self.public_key] {
+            bytes.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(field);
+        }
+        bytes.extend_from_slice(&self.amount.to_le_bytes());
+        bytes.extend_from_slice(&self.fee.to_le_bytes());
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        bytes.push(self.kind as u8);
+        match self.expires_at {
+            Some(expires_at) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&expires_at.to_le_bytes());
+            }
+            None => bytes.push(0),
+        }
+        match &self.memo {
+            Some(memo) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(memo.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(memo.as_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes
+    }
+
+    /// Size in bytes of the canonical encoding plus the signature.
+    pub fn serialized_size(&self) -> usize {
+        self.canonical_bytes().len() + self.signature.len()
+    }
+
+    /// Fee paid per byte of block space the transaction takes up.
+    pub fn fee_rate(&self) -> f64 {
+        self.fee / self.serialized_size() as f64
+    }
+
+    /// Hex-encoded SHA-256 of the canonical bytes followed by the
+    /// signature: the transaction's leaf in its block's merkle tree. Unlike
+    /// `id` it covers the signature, so a block commits to that as well,
+    /// while re-signing a transaction leaves its `id` unchanged.
+    pub fn witness_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.canonical_bytes());
+        hasher.update(&self.signature);
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn id(&self) -> TxId {
+        let mut hasher = Sha256::new();
+        hasher.update(self.canonical_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// Chained construction of a [`Transaction`], from [`Transaction::builder`]
+/// or `Blockchain::new_transaction`. Unset fields keep the defaults of
+/// `Transaction::new`: a transfer with no fee, nonce 0, no chain tag, no
+/// expiry and no memo.
+#[derive(Clone, Debug)]
+pub struct TransactionBuilder {
+    transaction: Transaction,
+}
+
+impl TransactionBuilder {
+    pub fn kind(mut self, kind: TransactionKind) -> Self {
+        self.transaction.kind = kind;
+        self
+    }
+
+    pub fn fee(mut self, fee: f64) -> Self {
+        self.transaction.fee = fee;
+        self
+    }
+
+    /// Must be the sender's `Blockchain::next_nonce` for the transaction to
+    /// be accepted by `add_transaction`.
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.transaction.nonce = nonce;
+        self
+    }
+
+    pub fn chain_tag(mut self, chain_id: String) -> Self {
+        self.transaction.chain_tag = chain_id;
+        self
+    }
+
+    pub fn expires_at(mut self, timestamp: i64) -> Self {
+        self.transaction.expires_at = Some(timestamp);
+        self
+    }
+
+    pub fn memo(mut self, memo: String) -> Self {
+        self.transaction.memo = Some(memo);
+        self
+    }
+
+    pub fn build(self) -> Transaction {
+        self.transaction
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Error)]
+pub enum TxRejectReason {
+    #[error("Insufficient balance")]
+    InsufficientBalance,
+    #[error("Amount must be a positive, finite number")]
+    InvalidAmount,
+    #[error("Sender and recipient are the same wallet")]
+    SelfTransfer,
+    #[error("Funds are staked and cannot be spent until unstaked")]
+    FundsStaked,
+    #[error("Insufficient staked balance")]
+    InsufficientStake,
+    #[error("Coinbase transactions are created by miners and cannot be submitted")]
+    CoinbaseNotAllowed,
+    #[error("Transaction is tagged for a different chain")]
+    WrongChain,
+    #[error("Transaction has already expired")]
+    Expired,
+    #[error("Memo is longer than {max} bytes")]
+    MemoTooLong { max: usize },
+    #[error("Mempool is full")]
+    MempoolFull,
+    #[error("Amount is below the dust threshold of {} tokens", format_amount(*threshold))]
+    Dust { threshold: f64 },
+    #[error("Amount exceeds the limit of {} tokens per transfer", format_amount(*max))]
+    AboveMaxAmount { max: f64 },
+    #[error("Transaction is not signed by the owner of the sending address")]
+    InvalidSignature,
+    #[error("Nonce does not match the sender's next nonce {expected}")]
+    WrongNonce { expected: u64 },
+    #[error("Fee must be a non-negative, finite number")]
+    InvalidFee,
+    #[error("Fee is below the minimum relay fee of {} tokens", format_amount(*min))]
+    FeeTooLow { min: f64 },
+    #[error(
+        "Balance cannot cover the fee of {} tokens plus the dust threshold of {} tokens",
+        format_amount(*fee),
+        format_amount(*threshold)
+    )]
+    SweepTooSmall { fee: f64, threshold: f64 },
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+
+    use super::*;
+
+    fn signed_transfer() -> Transaction {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let from = address_of(signing_key.verifying_key().as_bytes());
+        let mut transaction = Transaction::builder(from, String::from("0xrecipient"), 10.0).fee(0.5).build();
+        transaction.sign(&signing_key);
+        transaction
+    }
+
+    #[test]
+    fn signed_transaction_verifies() {
+        assert!(signed_transfer().verify());
+    }
+
+    #[test]
+    fn tampered_amount_fails_verification() {
+        let mut transaction = signed_transfer();
+        transaction.amount = 1000.0;
+        assert!(!transaction.verify());
+    }
+
+    #[test]
+    fn standalone_verification_catches_tampered_fields() {
+        let public_key = SigningKey::from_bytes(&[7; 32]).verifying_key();
+        let original = signed_transfer();
+        assert!(verify_transaction(&original, &public_key));
+        let tampers: [fn(&mut Transaction); 5] = [
+            |tx| tx.amount += 1.0,
+            |tx| tx.fee = 0.0,
+            |tx| tx.to = String::from("0xattacker"),
+            |tx| tx.nonce += 1,
+            |tx| tx.memo = Some(String::from("hi")),
+        ];
+        for tamper in tampers {
+            let mut transaction = original.clone();
+            tamper(&mut transaction);
+            assert!(!verify_transaction(&transaction, &public_key));
+        }
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert!(!verify_transaction(&original, &other));
+    }
+
+    #[test]
+    fn key_must_own_the_sending_address() {
+        let mut transaction = signed_transfer();
+        let other = SigningKey::from_bytes(&[8; 32]);
+        transaction.sign(&other);
+        assert!(!transaction.verify());
+    }
+
+    #[test]
+    fn resigning_keeps_the_id_but_not_the_witness_hash() {
+        let original = signed_transfer();
+        let mut transaction = original.clone();
+        transaction.signature[0] ^= 1;
+        assert_eq!(transaction.id(), original.id());
+        assert_ne!(transaction.witness_hash(), original.witness_hash());
+        assert!(!transaction.verify());
+
+        transaction.sign(&SigningKey::from_bytes(&[7; 32]));
+        assert_eq!(transaction.id(), original.id());
+        assert_eq!(transaction.witness_hash(), original.witness_hash());
+        assert!(transaction.verify());
+    }
+}